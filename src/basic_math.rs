@@ -1,173 +1,317 @@
 use crate::CmdResult;
-use crate::help;
+use crate::calculator::Calculator;
+use crate::command::{Builtin, Category};
+use crate::error::Error;
+use crate::logic_operators;
+use crate::modes;
 use crate::stack;
+use crate::stack::FractionType;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
 
 // Basic mathematical operations that work with both floats and fractions.
 
-fn add() -> CmdResult {
-    if let Some(values) = stack::get(
-        2,
-        stack::AcceptedTypes::FLOAT | stack::AcceptedTypes::FRACTIONS,
-    ) {
+const NUMBERS: stack::AcceptedTypes = stack::AcceptedTypes::FLOAT
+    .union(stack::AcceptedTypes::FRACTIONS)
+    .union(stack::AcceptedTypes::BIG);
+
+// Checked fraction arithmetic. Operands are expected to be reduced with a positive
+// denominator, which stack::push guarantees. Returns None if the result doesn't fit.
+
+pub fn fraction_add(a: FractionType, b: FractionType) -> Option<FractionType> {
+    // Cross-multiply with the smallest common denominator to keep numbers small.
+    let g = stack::gcd(a.1, b.1);
+    let scale_a = b.1 / g;
+    let scale_b = a.1 / g;
+    let num =
+        a.0.checked_mul(scale_a)?
+            .checked_add(b.0.checked_mul(scale_b)?)?;
+    let denom = a.1.checked_mul(scale_a)?;
+    Some((num, denom))
+}
+
+pub fn fraction_sub(a: FractionType, b: FractionType) -> Option<FractionType> {
+    fraction_add(a, (b.0.checked_neg()?, b.1))
+}
+
+pub fn fraction_mul(a: FractionType, b: FractionType) -> Option<FractionType> {
+    // Reduce crosswise before multiplying.
+    let g1 = stack::gcd(a.0, b.1);
+    let g2 = stack::gcd(b.0, a.1);
+    let num = (a.0 / g1).checked_mul(b.0 / g2)?;
+    let denom = (a.1 / g2).checked_mul(b.1 / g1)?;
+    Some((num, denom))
+}
+
+pub fn fraction_div(a: FractionType, b: FractionType) -> Option<FractionType> {
+    let reciprocal = if b.0 < 0 {
+        (b.1.checked_neg()?, b.0.checked_neg()?)
+    } else {
+        (b.1, b.0)
+    };
+    fraction_mul(a, reciprocal)
+}
+
+// In integer mode, integers are handled as words of the current width and binary mode. Returns
+// None if the operands aren't integers so they can be handled as usual.
+fn integer_arithmetic(
+    calc: &mut Calculator,
+    operation: fn(&BigInt, &BigInt) -> Option<BigInt>,
+) -> Option<CmdResult> {
+    if !calc.modes.get_integer_mode() {
+        return None;
+    }
+    let values = calc.stack.get(2, stack::AcceptedTypes::BIG)?;
+    let (a, b) = match (&values[0], &values[1]) {
+        (stack::StackValue::Big(a), stack::StackValue::Big(b))
+            if a.is_integer() && b.is_integer() =>
+        {
+            (a.to_integer(), b.to_integer())
+        }
+        _ => return None,
+    };
+    match operation(&a, &b) {
+        Some(result) => Some(logic_operators::push_integer(calc, 2, &result)),
+        None => Some(CmdResult::Error(Error::Domain("Division by zero"))),
+    }
+}
+
+// Replaces the top `count` values with the result of an exact operation, or handles the
+// overflow according to the current overflow mode. `big` calculates the same result with
+// arbitrary precision and is only called on overflow.
+pub fn push_fraction_result(
+    calc: &mut Calculator,
+    count: usize,
+    result: Option<FractionType>,
+    big: impl FnOnce() -> BigRational,
+) -> CmdResult {
+    match result {
+        Some(frac) => {
+            calc.stack.drop(count);
+            calc.stack.push(stack::StackValue::Fraction(frac));
+            CmdResult::Success
+        }
+        None => match calc.modes.get_overflow_mode() {
+            modes::OverflowMode::Exact => {
+                calc.stack.drop(count);
+                calc.stack.push(stack::StackValue::Big(big()));
+                CmdResult::Success
+            }
+            modes::OverflowMode::Promote => {
+                let inexact = big().to_f64().unwrap_or(f64::NAN);
+                calc.stack.drop(count);
+                calc.stack.push(stack::StackValue::Inexact(inexact));
+                CmdResult::Success
+            }
+            modes::OverflowMode::Error => CmdResult::Error(Error::Overflow("Fraction overflow")),
+        },
+    }
+}
+
+// Replaces the top `count` values with a float result. The result is inexact if one of the
+// values was, so an overflowed result doesn't turn exact again with the next operation.
+pub fn push_float(calc: &mut Calculator, count: usize, result: f64) {
+    let inexact = calc.stack.get_values(count).is_some_and(|values| {
+        values
+            .iter()
+            .any(|value| matches!(value, stack::StackValue::Inexact(_)))
+    });
+    calc.stack.drop(count);
+    calc.stack.push(if inexact {
+        stack::StackValue::Inexact(result)
+    } else {
+        stack::StackValue::Float(result)
+    });
+}
+
+pub fn add(calc: &mut Calculator) -> CmdResult {
+    if let Some(result) = integer_arithmetic(calc, |a, b| Some(a + b)) {
+        return result;
+    }
+    if let Some(values) = calc.stack.get(2, NUMBERS) {
         match (&values[0], &values[1]) {
-            (
-                stack::StackValue::Fraction((num1, denom1)),
-                stack::StackValue::Fraction((num2, denom2)),
-            ) => {
-                let result_num = num1 * denom2 + num2 * denom1;
-                let result_denom = denom1 * denom2;
-                stack::drop(2);
-                stack::push(stack::StackValue::Fraction((result_num, result_denom)));
+            (stack::StackValue::Fraction(a), stack::StackValue::Fraction(b)) => {
+                return push_fraction_result(calc, 2, fraction_add(*a, *b), || {
+                    stack::to_big(*a) + stack::to_big(*b)
+                });
+            }
+            (stack::StackValue::Big(a), stack::StackValue::Big(b)) => {
+                calc.stack.drop(2);
+                calc.stack.push(stack::StackValue::Big(a + b));
                 return CmdResult::Success;
             }
             (stack::StackValue::Float(f1), stack::StackValue::Float(f2)) => {
-                let result = f1 + f2;
-                stack::drop(2);
-                stack::push(stack::StackValue::Float(result));
+                push_float(calc, 2, f1 + f2);
                 return CmdResult::Success;
             }
-            _ => {
-                return CmdResult::Error("Unknown data types for addition");
-            }
+            _ => {}
         }
     }
-    CmdResult::Error("Not enough or wrong values on stack")
+    CmdResult::Error(calc.stack.get_error(2, NUMBERS))
 }
 
-fn div() -> CmdResult {
-    if let Some(values) = stack::get(
-        2,
-        stack::AcceptedTypes::FLOAT | stack::AcceptedTypes::FRACTIONS,
-    ) {
+pub fn div(calc: &mut Calculator) -> CmdResult {
+    // Integer division truncates towards zero, like on the HP-16C.
+    if let Some(result) = integer_arithmetic(calc, |a, b| (!b.is_zero()).then(|| a / b)) {
+        return result;
+    }
+    if let Some(values) = calc.stack.get(2, NUMBERS) {
         match (&values[0], &values[1]) {
-            (
-                stack::StackValue::Fraction((num1, denom1)),
-                stack::StackValue::Fraction((num2, denom2)),
-            ) => {
-                if *num2 == 0 {
-                    return CmdResult::Error("Division by zero");
+            (stack::StackValue::Fraction(a), stack::StackValue::Fraction(b)) => {
+                if b.0 == 0 {
+                    return CmdResult::Error(Error::Domain("Division by zero"));
+                }
+                return push_fraction_result(calc, 2, fraction_div(*a, *b), || {
+                    stack::to_big(*a) / stack::to_big(*b)
+                });
+            }
+            (stack::StackValue::Big(a), stack::StackValue::Big(b)) => {
+                if b.is_zero() {
+                    return CmdResult::Error(Error::Domain("Division by zero"));
                 }
-                let result_num = num1 * denom2;
-                let result_denom = denom1 * num2;
-                stack::drop(2);
-                stack::push(stack::StackValue::Fraction((result_num, result_denom)));
+                calc.stack.drop(2);
+                calc.stack.push(stack::StackValue::Big(a / b));
                 return CmdResult::Success;
             }
             (stack::StackValue::Float(f1), stack::StackValue::Float(f2)) => {
                 if *f2 == 0.0 {
-                    return CmdResult::Error("Division by zero");
+                    return CmdResult::Error(Error::Domain("Division by zero"));
                 }
-                let result = f1 / f2;
-                stack::drop(2);
-                stack::push(stack::StackValue::Float(result));
+                push_float(calc, 2, f1 / f2);
                 return CmdResult::Success;
             }
-            _ => {
-                return CmdResult::Error("Unknown data types for division");
-            }
+            _ => {}
         }
     }
-    CmdResult::Error("Not enough or wrong values on stack")
+    CmdResult::Error(calc.stack.get_error(2, NUMBERS))
 }
 
-fn mul() -> CmdResult {
-    if let Some(value) = stack::get(
-        2,
-        stack::AcceptedTypes::FLOAT | stack::AcceptedTypes::FRACTIONS,
-    ) {
+pub fn mul(calc: &mut Calculator) -> CmdResult {
+    if let Some(result) = integer_arithmetic(calc, |a, b| Some(a * b)) {
+        return result;
+    }
+    if let Some(value) = calc.stack.get(2, NUMBERS) {
         match (&value[0], &value[1]) {
-            (
-                stack::StackValue::Fraction((num1, denom1)),
-                stack::StackValue::Fraction((num2, denom2)),
-            ) => {
-                let result_num = num1 * num2;
-                let result_denom = denom1 * denom2;
-                stack::drop(2);
-                stack::push(stack::StackValue::Fraction((result_num, result_denom)));
+            (stack::StackValue::Fraction(a), stack::StackValue::Fraction(b)) => {
+                return push_fraction_result(calc, 2, fraction_mul(*a, *b), || {
+                    stack::to_big(*a) * stack::to_big(*b)
+                });
+            }
+            (stack::StackValue::Big(a), stack::StackValue::Big(b)) => {
+                calc.stack.drop(2);
+                calc.stack.push(stack::StackValue::Big(a * b));
                 return CmdResult::Success;
             }
             (stack::StackValue::Float(f1), stack::StackValue::Float(f2)) => {
-                let result = f1 * f2;
-                stack::drop(2);
-                stack::push(stack::StackValue::Float(result));
+                push_float(calc, 2, f1 * f2);
                 return CmdResult::Success;
             }
-            _ => {
-                return CmdResult::Error("Unknown data types for multiplication");
-            }
+            _ => {}
         }
     }
-    CmdResult::Error("Not enough or wrong values on stack")
+    CmdResult::Error(calc.stack.get_error(2, NUMBERS))
 }
 
-fn sub() -> CmdResult {
-    if let Some(value) = stack::get(
-        2,
-        stack::AcceptedTypes::FLOAT | stack::AcceptedTypes::FRACTIONS,
-    ) {
+pub fn sub(calc: &mut Calculator) -> CmdResult {
+    if let Some(result) = integer_arithmetic(calc, |a, b| Some(a - b)) {
+        return result;
+    }
+    if let Some(value) = calc.stack.get(2, NUMBERS) {
         match (&value[0], &value[1]) {
-            (
-                stack::StackValue::Fraction((num1, denom1)),
-                stack::StackValue::Fraction((num2, denom2)),
-            ) => {
-                let result_num = num1 * denom2 - num2 * denom1;
-                let result_denom = denom1 * denom2;
-                stack::drop(2);
-                stack::push(stack::StackValue::Fraction((result_num, result_denom)));
+            (stack::StackValue::Fraction(a), stack::StackValue::Fraction(b)) => {
+                return push_fraction_result(calc, 2, fraction_sub(*a, *b), || {
+                    stack::to_big(*a) - stack::to_big(*b)
+                });
+            }
+            (stack::StackValue::Big(a), stack::StackValue::Big(b)) => {
+                calc.stack.drop(2);
+                calc.stack.push(stack::StackValue::Big(a - b));
                 return CmdResult::Success;
             }
             (stack::StackValue::Float(f1), stack::StackValue::Float(f2)) => {
-                let result = f1 - f2;
-                stack::drop(2);
-                stack::push(stack::StackValue::Float(result));
+                push_float(calc, 2, f1 - f2);
                 return CmdResult::Success;
             }
-            _ => {
-                return CmdResult::Error("Unknown data types for subtraction");
-            }
+            _ => {}
         }
     }
-    CmdResult::Error("Not enough or wrong values on stack")
+    CmdResult::Error(calc.stack.get_error(2, NUMBERS))
 }
 
-pub fn commands(cmd: &str) -> CmdResult {
-    match cmd {
-        "add" => add(),
-        "sub" => sub(),
-        "mul" => mul(),
-        "div" => div(),
-        _ => CmdResult::NoMatch,
+pub const COMMANDS: Category = Category {
+    name: "Basic Math",
+    commands: &[
+        Builtin::new(
+            "add",
+            "Adds the top two numbers on the stack.",
+            |calc, _| add(calc),
+        )
+        .with_aliases(&["+"])
+        .with_operands(2, NUMBERS),
+        Builtin::new(
+            "sub",
+            "Subtracts the top two numbers on the stack.",
+            |calc, _| sub(calc),
+        )
+        .with_aliases(&["-"])
+        .with_operands(2, NUMBERS),
+        Builtin::new(
+            "mul",
+            "Multiplies the top two numbers on the stack.",
+            |calc, _| mul(calc),
+        )
+        .with_aliases(&["*"])
+        .with_operands(2, NUMBERS),
+        Builtin::new(
+            "div",
+            "Divides the top two numbers on the stack.",
+            |calc, _| div(calc),
+        )
+        .with_aliases(&["/"])
+        .with_operands(2, NUMBERS),
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fraction_arithmetic() {
+        assert_eq!(fraction_add((1, 2), (1, 3)), Some((5, 6)));
+        assert_eq!(fraction_sub((1, 2), (1, 3)), Some((1, 6)));
+        assert_eq!(fraction_mul((2, 3), (3, 4)), Some((1, 2)));
+        assert_eq!(fraction_div((1, 2), (-1, 4)), Some((-2, 1)));
     }
-}
 
-pub fn quick_commands(cmd: &char) -> CmdResult {
-    match cmd {
-        '+' => add(),
-        '-' => sub(),
-        '*' => mul(),
-        '/' => div(),
-        _ => CmdResult::NoMatch,
+    #[test]
+    fn test_fraction_overflow() {
+        assert_eq!(fraction_add((i128::MAX, 1), (1, 1)), None);
+        assert_eq!(fraction_mul((i128::MAX, 1), (2, 1)), None);
+        assert_eq!(fraction_sub((i128::MIN, 1), (1, 1)), None);
+        // Large values that only fit thanks to reduction before multiplying.
+        assert_eq!(fraction_mul((i128::MAX, 3), (3, i128::MAX)), Some((1, 1)));
+        assert_eq!(
+            fraction_add((1, i128::MAX), (1, i128::MAX)),
+            Some((2, i128::MAX))
+        );
     }
-}
 
-pub const HELP: help::Category = help::Category {
-    category: "Basic Math",
-    commands: &[
-        help::Cmd {
-            command: "add",
-            help: "Adds the top two numbers on the stack.",
-        },
-        help::Cmd {
-            command: "sub",
-            help: "Subtracts the top two numbers on the stack.",
-        },
-        help::Cmd {
-            command: "mul",
-            help: "Multiplies the top two numbers on the stack.",
-        },
-        help::Cmd {
-            command: "div",
-            help: "Divides the top two numbers on the stack.",
-        },
-    ],
-};
+    #[test]
+    fn test_inexact() {
+        let mut calc = Calculator::new();
+        let top = |calc: &Calculator| calc.stack.get_values(1).unwrap().remove(0);
+        assert_eq!(calc.exec("overflow_float"), Ok(()));
+        assert_eq!(calc.exec(&format!("{} 1 add", i128::MAX)), Ok(()));
+        assert!(matches!(top(&calc), stack::StackValue::Inexact(_)));
+
+        // Results stay inexact even when they are integers that would fit a fraction.
+        assert_eq!(calc.exec("10000000000 div"), Ok(()));
+        assert!(matches!(top(&calc), stack::StackValue::Inexact(f) if f > 1.7e28));
+        assert_eq!(calc.exec("2 mul 1 sub"), Ok(()));
+        assert!(matches!(top(&calc), stack::StackValue::Inexact(_)));
+        assert_eq!(calc.exec("drop 2.5 2 mul"), Ok(()));
+        assert_eq!(top(&calc), stack::StackValue::Fraction((5, 1)));
+    }
+}
@@ -0,0 +1,240 @@
+use crate::CmdResult;
+use crate::calculator::Calculator;
+use crate::command::{Builtin, Category};
+use crate::error::Error;
+use crate::logic_operators::{self, INTEGER_TYPES};
+
+// Bit field manipulation of the integer in X, using the same word handling as the logic
+// operators. Bit numbers and field limits can be given inline (`bit_set 3`) or taken from the
+// stack, in which case they are above the operands with the last argument in X.
+
+// Operations get the words, the arguments and the word size in bits.
+type FieldOperation = fn(&[u128], &[u32], u32) -> Result<u128, Error>;
+
+fn bit(n: u32, bits: u32) -> Result<u128, Error> {
+    if n < bits {
+        Ok(1 << n)
+    } else {
+        Err(Error::Argument("Bit number out of range"))
+    }
+}
+
+fn low_ones(n: u32) -> u128 {
+    if n >= 128 { u128::MAX } else { (1 << n) - 1 }
+}
+
+fn field_mask(hi: u32, lo: u32, bits: u32) -> Result<u128, Error> {
+    if hi < lo || hi >= bits {
+        return Err(Error::Argument("Invalid bit field"));
+    }
+    Ok(low_ones(hi - lo + 1) << lo)
+}
+
+fn field_command(
+    calc: &mut Calculator,
+    args: &[&str],
+    arg_count: usize,
+    operand_count: usize,
+    operation: FieldOperation,
+) -> CmdResult {
+    // Inline arguments were checked by command::run.
+    let stack_args = if args.is_empty() { arg_count } else { 0 };
+
+    let words = match logic_operators::get_words(calc, operand_count + stack_args) {
+        Ok(words) => words,
+        Err(e) => return CmdResult::Error(e),
+    };
+
+    let mut arguments = Vec::with_capacity(arg_count);
+    if stack_args == 0 {
+        for arg in args {
+            match arg.parse::<u32>() {
+                Ok(n) => arguments.push(n),
+                Err(_) => return CmdResult::Error(Error::Argument("Invalid bit number")),
+            }
+        }
+    } else {
+        for word in &words[operand_count..] {
+            match u32::try_from(*word) {
+                Ok(n) => arguments.push(n),
+                Err(_) => return CmdResult::Error(Error::Argument("Invalid bit number")),
+            }
+        }
+    }
+
+    let bits = calc.modes.get_binary_width().bits();
+    match operation(&words[..operand_count], &arguments, bits) {
+        Ok(result) => logic_operators::push_word(calc, operand_count + stack_args, result),
+        Err(e) => CmdResult::Error(e),
+    }
+}
+
+fn bit_set(words: &[u128], args: &[u32], bits: u32) -> Result<u128, Error> {
+    Ok(words[0] | bit(args[0], bits)?)
+}
+
+fn bit_clear(words: &[u128], args: &[u32], bits: u32) -> Result<u128, Error> {
+    Ok(words[0] & !bit(args[0], bits)?)
+}
+
+fn bit_toggle(words: &[u128], args: &[u32], bits: u32) -> Result<u128, Error> {
+    Ok(words[0] ^ bit(args[0], bits)?)
+}
+
+fn bit_test(words: &[u128], args: &[u32], bits: u32) -> Result<u128, Error> {
+    Ok((words[0] & bit(args[0], bits)? != 0) as u128)
+}
+
+fn extract(words: &[u128], args: &[u32], bits: u32) -> Result<u128, Error> {
+    Ok((words[0] & field_mask(args[0], args[1], bits)?) >> args[1])
+}
+
+// Deposits Y into the field of X.
+fn insert(words: &[u128], args: &[u32], bits: u32) -> Result<u128, Error> {
+    let mask = field_mask(args[0], args[1], bits)?;
+    Ok((words[1] & !mask) | ((words[0] << args[1]) & mask))
+}
+
+fn mask(_: &[u128], args: &[u32], bits: u32) -> Result<u128, Error> {
+    if args[0] > bits {
+        return Err(Error::Argument("Mask wider than word size"));
+    }
+    Ok(low_ones(args[0]))
+}
+
+fn popcount(words: &[u128], _: &[u32], _: u32) -> Result<u128, Error> {
+    Ok(words[0].count_ones() as u128)
+}
+
+fn clz(words: &[u128], _: &[u32], bits: u32) -> Result<u128, Error> {
+    Ok((words[0].leading_zeros() - (128 - bits)) as u128)
+}
+
+fn ctz(words: &[u128], _: &[u32], bits: u32) -> Result<u128, Error> {
+    Ok(words[0].trailing_zeros().min(bits) as u128)
+}
+
+fn bitreverse(words: &[u128], _: &[u32], bits: u32) -> Result<u128, Error> {
+    Ok(words[0].reverse_bits() >> (128 - bits))
+}
+
+fn byteswap(words: &[u128], _: &[u32], bits: u32) -> Result<u128, Error> {
+    if !bits.is_multiple_of(8) {
+        return Err(Error::Failed("Word size is not a whole number of bytes"));
+    }
+    Ok(words[0].swap_bytes() >> (128 - bits))
+}
+
+pub const COMMANDS: Category = Category {
+    name: "Bit Fields",
+    commands: &[
+        Builtin::new(
+            "bit_set",
+            "Sets bit n of the top integer on the stack.",
+            |calc, args| field_command(calc, args, 1, 1, bit_set),
+        )
+        .with_arguments(&["n"])
+        .with_operands(1, INTEGER_TYPES),
+        Builtin::new(
+            "bit_clear",
+            "Clears bit n of the top integer on the stack.",
+            |calc, args| field_command(calc, args, 1, 1, bit_clear),
+        )
+        .with_arguments(&["n"])
+        .with_operands(1, INTEGER_TYPES),
+        Builtin::new(
+            "bit_test",
+            "Replaces the top integer on the stack with 1 if bit n is set, otherwise 0.",
+            |calc, args| field_command(calc, args, 1, 1, bit_test),
+        )
+        .with_arguments(&["n"])
+        .with_operands(1, INTEGER_TYPES),
+        Builtin::new(
+            "bit_toggle",
+            "Toggles bit n of the top integer on the stack.",
+            |calc, args| field_command(calc, args, 1, 1, bit_toggle),
+        )
+        .with_arguments(&["n"])
+        .with_operands(1, INTEGER_TYPES),
+        Builtin::new(
+            "extract",
+            "Replaces the top integer on the stack with bits hi..lo, shifted down.",
+            |calc, args| field_command(calc, args, 2, 1, extract),
+        )
+        .with_arguments(&["hi", "lo"])
+        .with_operands(1, INTEGER_TYPES),
+        Builtin::new(
+            "insert",
+            "Deposits the second integer into bits hi..lo of the top integer on the stack.",
+            |calc, args| field_command(calc, args, 2, 2, insert),
+        )
+        .with_arguments(&["hi", "lo"])
+        .with_operands(2, INTEGER_TYPES),
+        Builtin::new(
+            "mask",
+            "Pushes an integer with the n lowest bits set.",
+            |calc, args| field_command(calc, args, 1, 0, mask),
+        )
+        .with_arguments(&["n"])
+        .with_operands(0, INTEGER_TYPES),
+        Builtin::new(
+            "popcount",
+            "Counts the set bits of the top integer on the stack.",
+            |calc, args| field_command(calc, args, 0, 1, popcount),
+        )
+        .with_operands(1, INTEGER_TYPES),
+        Builtin::new(
+            "clz",
+            "Counts the leading zeros of the top integer on the stack.",
+            |calc, args| field_command(calc, args, 0, 1, clz),
+        )
+        .with_operands(1, INTEGER_TYPES),
+        Builtin::new(
+            "ctz",
+            "Counts the trailing zeros of the top integer on the stack.",
+            |calc, args| field_command(calc, args, 0, 1, ctz),
+        )
+        .with_operands(1, INTEGER_TYPES),
+        Builtin::new(
+            "bitreverse",
+            "Reverses the bit order of the top integer on the stack.",
+            |calc, args| field_command(calc, args, 0, 1, bitreverse),
+        )
+        .with_operands(1, INTEGER_TYPES),
+        Builtin::new(
+            "byteswap",
+            "Reverses the byte order of the top integer on the stack.",
+            |calc, args| field_command(calc, args, 0, 1, byteswap),
+        )
+        .with_operands(1, INTEGER_TYPES),
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 32 bit words.
+
+    #[test]
+    fn test_fields() {
+        assert_eq!(extract(&[0xdead_beef], &[15, 8], 32), Ok(0xbe));
+        assert_eq!(insert(&[0x12, 0xdead_beef], &[15, 8], 32), Ok(0xdead_12ef));
+        assert_eq!(
+            extract(&[0xdead_beef], &[8, 15], 32),
+            Err(Error::Argument("Invalid bit field"))
+        );
+        assert_eq!(
+            bit_set(&[0], &[32], 32),
+            Err(Error::Argument("Bit number out of range"))
+        );
+    }
+
+    #[test]
+    fn test_counts() {
+        assert_eq!(clz(&[0x0000_ffff], &[], 32), Ok(16));
+        assert_eq!(ctz(&[0], &[], 32), Ok(32));
+        assert_eq!(bitreverse(&[1], &[], 32), Ok(0x8000_0000));
+        assert_eq!(byteswap(&[0x1234_5678], &[], 32), Ok(0x7856_3412));
+    }
+}
@@ -0,0 +1,257 @@
+use crate::CmdResult;
+use crate::command::{self, Registry};
+use crate::error::{CommandError, Error};
+use crate::literals;
+use crate::modes::Modes;
+use crate::stack::{History, Stack};
+use std::path::PathBuf;
+
+// What a frontend shows instead of the stack, set by commands like help. Frontends switch back
+// to the stack on the next input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum View {
+    Stack,
+    Help,
+    Variables,
+    History,
+}
+
+// A calculator with its own stack, variables, undo history and modes.
+pub struct Calculator {
+    pub stack: Stack,
+    pub history: History,
+    pub modes: Modes,
+    pub view: View,
+    pub registry: Registry,
+    // The scripts being run, innermost last.
+    pub scripts: Vec<PathBuf>,
+}
+
+// Splits an input line into tokens. Commands taking inline arguments ("bit_set 3") keep them in
+// the same token, as long as they are plain numbers. Otherwise the arguments are taken from the
+// stack when the command runs. Text arguments ("run file") always stay with their command.
+pub fn tokenize(registry: &Registry, input: &str) -> Vec<String> {
+    let words: Vec<&str> = input.split_whitespace().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < words.len() {
+        let command = registry.find(words[i]);
+        let arguments = command.as_ref().map_or(&[][..], |c| c.arguments());
+        let count = arguments.len();
+        let args = &words[i + 1..(i + 1 + count).min(words.len())];
+        if count > 0
+            && args.len() == count
+            && arguments.iter().zip(args).all(|(argument, arg)| {
+                command::is_text_argument(argument) || arg.chars().all(|c| c.is_ascii_digit())
+            })
+        {
+            tokens.push(words[i..=i + count].join(" "));
+            i += count + 1;
+        } else {
+            tokens.push(words[i].to_string());
+            i += 1;
+        }
+    }
+    tokens
+}
+
+impl Default for Calculator {
+    fn default() -> Calculator {
+        Calculator::new()
+    }
+}
+
+impl Calculator {
+    pub fn new() -> Calculator {
+        Calculator {
+            stack: Stack::default(),
+            history: History::default(),
+            modes: Modes::default(),
+            view: View::Stack,
+            registry: Registry::default(),
+            scripts: Vec::new(),
+        }
+    }
+
+    // Executes all tokens of an input line left to right. If one fails, the stack, modes and view
+    // are rolled back to where they were before the line.
+    pub fn exec(&mut self, input: &str) -> Result<(), CommandError> {
+        let snapshot = (
+            self.stack.clone(),
+            self.history.clone(),
+            self.modes,
+            self.view,
+        );
+        for token in tokenize(&self.registry, input) {
+            if let Err(e) = self.execute(&token) {
+                (self.stack, self.history, self.modes, self.view) = snapshot;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    // Runs a single command or number as one undo step.
+    fn execute(&mut self, token: &str) -> Result<(), CommandError> {
+        self.history.begin_command(&self.stack);
+        let result = self.execute_command(token);
+        self.history.end_command(&self.stack, token, result.is_ok());
+        result.map_err(|error| CommandError {
+            command: token.to_string(),
+            error,
+        })
+    }
+
+    fn execute_command(&mut self, token: &str) -> Result<(), Error> {
+        // 'rate recalls a variable, the quote keeps names apart from commands.
+        let words: Vec<&str> = match token.strip_prefix('\'') {
+            Some(name) if !name.contains('\'') => vec!["rcl", name],
+            _ => token.split(' ').collect(),
+        };
+
+        if let Some(command) = self.registry.find(words[0]) {
+            return match command::run(command.as_ref(), self, &words[1..]) {
+                CmdResult::Success => Ok(()),
+                CmdResult::Error(e) => Err(e),
+            };
+        }
+
+        match literals::parse(token) {
+            Some(Ok(value)) => {
+                self.stack.push(value);
+                Ok(())
+            }
+            Some(Err(reason)) => Err(Error::InvalidNumber {
+                token: token.to_string(),
+                reason,
+            }),
+            None => Err(Error::UnknownCommand {
+                name: token.to_string(),
+                suggestions: self.get_suggestions(token),
+            }),
+        }
+    }
+
+    // Commands and variables that might have been meant by an unknown token. Variables are
+    // suggested as the command recalling them, rcl name.
+    fn get_suggestions(&self, token: &str) -> Vec<String> {
+        let word = token.trim_start_matches('\'');
+        let variables: Vec<String> = self
+            .stack
+            .get_variables()
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| name.starts_with(|c: char| c.is_alphabetic()))
+            .collect();
+        let mut suggestions = command::get_suggestions(word, self.registry.get_names().into_iter());
+        suggestions.extend(
+            command::get_suggestions(word, variables.iter().map(String::as_str))
+                .iter()
+                .map(|name| format!("rcl {}", name)),
+        );
+        suggestions
+    }
+
+    // The stack as shown by the frontends, top value last. Lines wider than `width` are wrapped.
+    pub fn get_stack_contents(&self, width: usize) -> Vec<String> {
+        self.stack.get_stack_contents(width, &self.modes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stack::StackValue;
+
+    #[test]
+    fn test_tokenize() {
+        let registry = Registry::default();
+        let tokenize = |input| tokenize(&registry, input);
+        assert_eq!(tokenize("3  4 add"), vec!["3", "4", "add"]);
+        assert_eq!(
+            tokenize("hff extract 7 4 bit_set 1"),
+            vec!["hff", "extract 7 4", "bit_set 1"]
+        );
+        assert_eq!(
+            tokenize("5 3 bit_set dup"),
+            vec!["5", "3", "bit_set", "dup"]
+        );
+        assert_eq!(tokenize("run lib.rpn 2"), vec!["run lib.rpn", "2"]);
+    }
+
+    #[test]
+    fn test_exec() {
+        let mut calc = Calculator::new();
+        assert_eq!(calc.exec("1 2 add 3"), Ok(()));
+        assert!(matches!(
+            calc.exec("4 nosuch").map_err(|e| e.error),
+            Err(Error::UnknownCommand { .. })
+        ));
+        assert_eq!(
+            calc.stack.get_values(calc.stack.depth()),
+            Some(vec![
+                StackValue::Fraction((3, 1)),
+                StackValue::Fraction((3, 1))
+            ])
+        );
+
+        // Mode changes are rolled back as well.
+        assert!(calc.exec("hex 1 nosuch").is_err());
+        assert!(matches!(calc.modes.get_radix(), crate::modes::Radix::Dec));
+        assert_eq!(calc.stack.depth(), 2);
+
+        // Calculators don't share anything.
+        let other = Calculator::new();
+        assert_eq!(other.stack.depth(), 0);
+        assert_eq!(calc.history.get_commands().0, vec!["1", "2", "add", "3"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut calc = Calculator::new();
+        let error = calc.exec("1 add").unwrap_err();
+        assert_eq!(
+            error.error,
+            Error::StackUnderflow {
+                expected: 2,
+                actual: 1
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "add: Not enough values on stack, needs 2 but has 1"
+        );
+        assert!(matches!(
+            calc.exec("1/2 1 and").unwrap_err().error,
+            Error::TypeMismatch {
+                found: "fraction",
+                ..
+            }
+        ));
+        assert_eq!(
+            calc.exec("1 0 div").unwrap_err().error,
+            Error::Domain("Division by zero")
+        );
+        assert!(matches!(
+            calc.exec("1x").unwrap_err().error,
+            Error::InvalidNumber { .. }
+        ));
+
+        assert_eq!(
+            calc.exec("sawp").unwrap_err().to_string(),
+            "Unknown command 'sawp' — did you mean 'swap'?"
+        );
+        assert_eq!(calc.exec("5 sto rate"), Ok(()));
+        assert_eq!(
+            calc.exec("rtae").unwrap_err().error,
+            Error::UnknownCommand {
+                name: "rtae".to_string(),
+                suggestions: vec!["rcl rate".to_string()]
+            }
+        );
+        assert_eq!(
+            calc.exec("rtae").unwrap_err().to_string(),
+            "Unknown command 'rtae' — did you mean 'rcl rate'?"
+        );
+    }
+}
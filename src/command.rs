@@ -0,0 +1,361 @@
+use crate::CmdResult;
+use crate::calculator::Calculator;
+use crate::error::Error;
+use crate::stack::AcceptedTypes;
+use std::sync::Arc;
+
+// Commands are looked up in a Registry, which is also where the help text, the inline arguments
+// and the checks before running a command come from. Built-in commands are Builtin entries in
+// the COMMANDS table of their module, other commands can implement the trait themselves.
+
+pub trait Command: Send + Sync {
+    fn name(&self) -> &str;
+
+    // Other names the command can be called by, e.g. + for add.
+    fn aliases(&self) -> &[&str] {
+        &[]
+    }
+
+    // Placeholders for the inline arguments, e.g. ["hi", "lo"] for extract. Numeric arguments
+    // can also be taken from the stack, text arguments like "name" must be given.
+    fn arguments(&self) -> &[&str] {
+        &[]
+    }
+
+    // The number of stack values the command works on and the types they may have, checked
+    // before it runs. Commands working on a variable number of values check them themselves.
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn accepted_types(&self) -> AcceptedTypes {
+        AcceptedTypes::all()
+    }
+
+    fn help(&self) -> &str;
+
+    fn exec(&self, calc: &mut Calculator, args: &[&str]) -> CmdResult;
+}
+
+// Argument placeholders that stand for any word rather than a number.
+const TEXT_ARGUMENTS: &[&str] = &["file", "name"];
+
+pub fn is_text_argument(argument: &str) -> bool {
+    TEXT_ARGUMENTS.contains(&argument)
+}
+
+// A built-in command. The table entries are built with new() and the with_* functions, e.g.
+//   Builtin::new("add", "Adds ...", |calc, _| add(calc)).with_operands(2, NUMBERS)
+#[derive(Clone, Copy)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub arguments: &'static [&'static str],
+    pub arity: usize,
+    pub types: AcceptedTypes,
+    pub help: &'static str,
+    pub exec: fn(&mut Calculator, &[&str]) -> CmdResult,
+}
+
+impl Builtin {
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        exec: fn(&mut Calculator, &[&str]) -> CmdResult,
+    ) -> Builtin {
+        Builtin {
+            name,
+            aliases: &[],
+            arguments: &[],
+            arity: 0,
+            types: AcceptedTypes::all(),
+            help,
+            exec,
+        }
+    }
+
+    pub const fn with_aliases(self, aliases: &'static [&'static str]) -> Builtin {
+        Builtin { aliases, ..self }
+    }
+
+    pub const fn with_arguments(self, arguments: &'static [&'static str]) -> Builtin {
+        Builtin { arguments, ..self }
+    }
+
+    pub const fn with_operands(self, arity: usize, types: AcceptedTypes) -> Builtin {
+        Builtin {
+            arity,
+            types,
+            ..self
+        }
+    }
+}
+
+impl Command for Builtin {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn aliases(&self) -> &[&str] {
+        self.aliases
+    }
+
+    fn arguments(&self) -> &[&str] {
+        self.arguments
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn accepted_types(&self) -> AcceptedTypes {
+        self.types
+    }
+
+    fn help(&self) -> &str {
+        self.help
+    }
+
+    fn exec(&self, calc: &mut Calculator, args: &[&str]) -> CmdResult {
+        (self.exec)(calc, args)
+    }
+}
+
+// The built-in commands of a module, listed together in the help.
+pub struct Category {
+    pub name: &'static str,
+    pub commands: &'static [Builtin],
+}
+
+const BUILTINS: &[&Category] = &[
+    &crate::basic_math::COMMANDS,
+    &crate::scientific::COMMANDS,
+    &crate::logic_operators::COMMANDS,
+    &crate::bit_fields::COMMANDS,
+    &crate::modes::COMMANDS,
+    &crate::stack_manipulations::COMMANDS,
+    &crate::registers::COMMANDS,
+    &crate::session::COMMANDS,
+    &crate::script::COMMANDS,
+    &crate::help::COMMANDS,
+];
+
+// All commands a calculator knows, by category in the order they were registered.
+#[derive(Clone)]
+pub struct Registry {
+    categories: Vec<(String, Vec<Arc<dyn Command>>)>,
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        let mut registry = Registry::empty();
+        for category in BUILTINS {
+            for builtin in category.commands {
+                registry.register(category.name, Arc::new(*builtin));
+            }
+        }
+        registry
+    }
+}
+
+impl Registry {
+    pub fn empty() -> Registry {
+        Registry {
+            categories: Vec::new(),
+        }
+    }
+
+    // Adds a command to a category, which is created if needed. A command with the same name
+    // as an existing one replaces it.
+    pub fn register(&mut self, category: &str, command: Arc<dyn Command>) {
+        for (_, commands) in &mut self.categories {
+            commands.retain(|c| c.name() != command.name());
+        }
+        match self
+            .categories
+            .iter_mut()
+            .find(|(name, _)| name == category)
+        {
+            Some((_, commands)) => commands.push(command),
+            None => self.categories.push((category.to_string(), vec![command])),
+        }
+    }
+
+    // Finds a command by its name or one of its aliases.
+    pub fn find(&self, name: &str) -> Option<Arc<dyn Command>> {
+        self.categories
+            .iter()
+            .flat_map(|(_, commands)| commands)
+            .find(|c| c.name() == name || c.aliases().contains(&name))
+            .cloned()
+    }
+
+    pub fn get_categories(&self) -> impl Iterator<Item = (&str, &[Arc<dyn Command>])> {
+        self.categories
+            .iter()
+            .map(|(name, commands)| (name.as_str(), commands.as_slice()))
+    }
+
+    // The names and aliases of all commands.
+    pub fn get_names(&self) -> Vec<&str> {
+        self.categories
+            .iter()
+            .flat_map(|(_, commands)| commands)
+            .flat_map(|c| std::iter::once(c.name()).chain(c.aliases().iter().copied()))
+            .collect()
+    }
+}
+
+// The number of single character insertions, deletions, substitutions and swaps of neighbouring
+// characters needed to turn one word into the other.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Rows of the distances between prefixes of a and b, for the last two and the current prefix
+    // of a.
+    let mut before: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let substitution = previous[j - 1] + (a[i - 1] != b[j - 1]) as usize;
+            current[j] = substitution.min(previous[j] + 1).min(current[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        before = std::mem::replace(&mut previous, current);
+    }
+    previous[b.len()]
+}
+
+// The candidates close enough to `word` to be what was meant, closest first. Longer words may
+// have more typos.
+pub fn get_suggestions<'a>(word: &str, candidates: impl Iterator<Item = &'a str>) -> Vec<String> {
+    const MAX_SUGGESTIONS: usize = 3;
+    let max_distance = (word.chars().count() / 3).max(1);
+    let mut suggestions: Vec<(usize, &str)> = candidates
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    suggestions.sort();
+    suggestions.dedup();
+    suggestions
+        .iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate.to_string())
+        .collect()
+}
+
+// Runs a command after checking its arguments and the values on the stack, so the commands
+// themselves can rely on them.
+pub fn run(command: &dyn Command, calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    let arguments = command.arguments();
+    if args.len() != arguments.len() {
+        if !args.is_empty() {
+            return CmdResult::Error(Error::Argument("Wrong number of arguments"));
+        }
+        if arguments.iter().any(|argument| is_text_argument(argument)) {
+            return CmdResult::Error(Error::Argument("Missing argument"));
+        }
+    }
+
+    let (count, types) = (command.arity(), command.accepted_types());
+    match calc.stack.get_values(count) {
+        Some(values) if values.iter().all(|value| types.accepts(value)) => {}
+        _ => return CmdResult::Error(calc.stack.get_error(count, types)),
+    }
+
+    command.exec(calc, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stack::StackValue;
+
+    struct Answer;
+
+    impl Command for Answer {
+        fn name(&self) -> &str {
+            "answer"
+        }
+
+        fn help(&self) -> &str {
+            "Pushes 42."
+        }
+
+        fn exec(&self, calc: &mut Calculator, _: &[&str]) -> CmdResult {
+            calc.stack.push(StackValue::Integer(42));
+            CmdResult::Success
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = Registry::default();
+        assert_eq!(
+            registry.find("+").map(|c| c.name().to_string()),
+            Some("add".into())
+        );
+        assert!(registry.find("answer").is_none());
+
+        registry.register("Custom", Arc::new(Answer));
+        assert!(registry.get_names().contains(&"answer"));
+        assert_eq!(registry.get_categories().last().unwrap().0, "Custom");
+
+        // Every name is unique, otherwise commands would hide each other.
+        let mut names = registry.get_names();
+        let count = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), count);
+    }
+
+    #[test]
+    fn test_suggestions() {
+        assert_eq!(edit_distance("swap", "swap"), 0);
+        assert_eq!(edit_distance("sawp", "swap"), 1);
+        assert_eq!(edit_distance("dupp", "dup"), 1);
+        assert_eq!(edit_distance("", "rot"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+
+        let registry = Registry::default();
+        let suggest = |word| get_suggestions(word, registry.get_names().into_iter());
+        assert_eq!(suggest("sawp"), vec!["swap"]);
+        assert_eq!(suggest("hexx"), vec!["hex"]);
+        assert!(suggest("xyzzy").is_empty());
+
+        let mut calc = Calculator::new();
+        assert_eq!(calc.exec("5 sto rate"), Ok(()));
+        assert_eq!(
+            calc.exec("rote").unwrap_err().to_string(),
+            "Unknown command 'rote' — did you mean 'rot' or 'rcl rate'?"
+        );
+    }
+
+    #[test]
+    fn test_checks() {
+        let mut calc = Calculator::new();
+        let add = calc.registry.find("add").unwrap();
+        let store = calc.registry.find("sto").unwrap();
+        assert!(matches!(
+            run(add.as_ref(), &mut calc, &[]),
+            CmdResult::Error(Error::StackUnderflow {
+                expected: 2,
+                actual: 0
+            })
+        ));
+        assert!(matches!(
+            run(store.as_ref(), &mut calc, &[]),
+            CmdResult::Error(Error::Argument("Missing argument"))
+        ));
+        calc.stack.push(StackValue::Float(0.0001));
+        calc.stack.push(StackValue::Integer(1));
+        let and = calc.registry.find("and").unwrap();
+        assert!(matches!(
+            run(and.as_ref(), &mut calc, &[]),
+            CmdResult::Error(Error::TypeMismatch { found: "float", .. })
+        ));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::calculator::Calculator;
+use crate::command::Command;
+use std::sync::Arc;
+
+// Tab completion and hints for the input line while it's typed. The word at the end of the line
+// is completed from the command names, or from the variable names after a quote or for commands
+// taking a name. Hints show how the command being typed is used.
+
+// The words of the input, the last one being the word that is typed, empty after a space.
+fn get_words(input: &str) -> Vec<&str> {
+    let mut words: Vec<&str> = input.split_whitespace().collect();
+    if input.is_empty() || input.ends_with(char::is_whitespace) {
+        words.push("");
+    }
+    words
+}
+
+// The command the typed word is an inline argument of, with the index of that argument.
+fn get_argument_of(calc: &Calculator, words: &[&str]) -> Option<(Arc<dyn Command>, usize)> {
+    let current = words.len() - 1;
+    for i in (0..current).rev() {
+        if let Some(command) = calc.registry.find(words[i]) {
+            let index = current - i - 1;
+            return (index < command.arguments().len()).then_some((command, index));
+        }
+        // Any other word might be an argument, except a recalled variable ('name), which ends
+        // the search.
+        if words[i].starts_with('\'') {
+            return None;
+        }
+    }
+    None
+}
+
+fn get_variable_names(calc: &Calculator) -> Vec<String> {
+    calc.stack
+        .get_variables()
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| name.starts_with(|c: char| c.is_alphabetic()))
+        .collect()
+}
+
+// The possible completions of the typed word, sorted.
+pub fn get_candidates(calc: &Calculator, input: &str) -> Vec<String> {
+    let words = get_words(input);
+    let word = words[words.len() - 1];
+    let names: Vec<String> = match get_argument_of(calc, &words) {
+        Some((command, index)) if command.arguments()[index] == "name" => get_variable_names(calc),
+        // Numbers and file names aren't completed.
+        Some(_) => Vec::new(),
+        None if word.starts_with('\'') => get_variable_names(calc)
+            .iter()
+            .map(|name| format!("'{}", name))
+            .collect(),
+        None => calc
+            .registry
+            .get_names()
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+    };
+
+    let mut candidates: Vec<String> = names
+        .into_iter()
+        .filter(|name| name.starts_with(word))
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let length = a
+        .char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((i, _), _)| i);
+    &a[..length]
+}
+
+// Completes the typed word as far as all candidates agree, with a space after a word that is
+// complete. Returns the new input and the candidates.
+pub fn complete(calc: &Calculator, input: &str) -> (String, Vec<String>) {
+    let candidates = get_candidates(calc, input);
+    let words = get_words(input);
+    let word = words[words.len() - 1];
+    let start = input.len() - word.len();
+    let completed = match candidates.as_slice() {
+        [] => return (input.to_string(), candidates),
+        [candidate] => format!("{} ", candidate),
+        [first, rest @ ..] => rest
+            .iter()
+            .fold(first.as_str(), |prefix, candidate| {
+                common_prefix(prefix, candidate)
+            })
+            .to_string(),
+    };
+    (format!("{}{}", &input[..start], completed), candidates)
+}
+
+// How a command is used, e.g. "extract hi lo (1 value): Replaces ...".
+pub fn describe(command: &dyn Command) -> String {
+    let mut usage = vec![command.name()];
+    usage.extend(command.arguments());
+    let usage = usage.join(" ");
+    match command.arity() {
+        0 => format!("{}: {}", usage, command.help()),
+        1 => format!("{} (1 value): {}", usage, command.help()),
+        n => format!("{} ({} values): {}", usage, n, command.help()),
+    }
+}
+
+// A hint for the command being typed: the typed word if it's a command or the only command it
+// can be completed to, or the command whose arguments are typed.
+pub fn get_hint(calc: &Calculator, input: &str) -> Option<String> {
+    let words = get_words(input);
+    let word = words[words.len() - 1];
+    if !word.is_empty() {
+        if let Some(command) = calc.registry.find(word) {
+            return Some(describe(command.as_ref()));
+        }
+        if let [candidate] = get_candidates(calc, input).as_slice()
+            && let Some(command) = calc.registry.find(candidate)
+        {
+            return Some(describe(command.as_ref()));
+        }
+    }
+    get_argument_of(calc, &words).map(|(command, _)| describe(command.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete() {
+        let mut calc = Calculator::new();
+        assert_eq!(calc.exec("5 sto rate 6 sto ratio 7 sto 12"), Ok(()));
+
+        assert_eq!(get_candidates(&calc, "1 sw"), vec!["swap"]);
+        assert_eq!(complete(&calc, "1 sw").0, "1 swap ");
+        assert_eq!(complete(&calc, "sin").1, vec!["sin", "sinh"]);
+        assert_eq!(complete(&calc, "bit_t").0, "bit_t");
+        assert_eq!(complete(&calc, "ove").0, "over");
+        assert_eq!(complete(&calc, "overf").0, "overflow_");
+        assert_eq!(complete(&calc, "'r").1, vec!["'rate", "'ratio"]);
+        assert_eq!(complete(&calc, "'r").0, "'rat");
+        assert_eq!(complete(&calc, "sto+ ra").0, "sto+ rat");
+        assert!(get_candidates(&calc, "extract 7 ").is_empty());
+        assert!(get_candidates(&calc, "xyz").is_empty());
+    }
+
+    #[test]
+    fn test_hint() {
+        let calc = Calculator::new();
+        assert_eq!(
+            get_hint(&calc, "1 2 swap"),
+            Some("swap (2 values): Swaps the top two values on the stack.".to_string())
+        );
+        assert!(get_hint(&calc, "swa").unwrap().starts_with("swap"));
+        assert!(
+            get_hint(&calc, "extract 7 ")
+                .unwrap()
+                .starts_with("extract hi lo (1 value)")
+        );
+        assert_eq!(get_hint(&calc, "extract 7 4 "), None);
+        assert_eq!(get_hint(&calc, "s"), None);
+    }
+}
@@ -0,0 +1,322 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use lazy_static::lazy_static;
+use ratatui::style::Color;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use rpn::{Calculator, CmdResult, modes};
+
+// The configuration file, rpn/config.toml in the XDG config directory. For example:
+//
+//   # Default modes, set at startup. The modes of a restored session override them.
+//   modes = ["signed", "width 16", "hex", "fix 4"]
+//   undo_depth = 100
+//
+//   [colors]
+//   status_background = "blue"
+//   error_background = "#aa0000"
+//
+//   [keys]
+//   "s" = "swap"
+//   "ctrl-z" = "undo"
+//   "ctrl-y" = "redo"
+//   "f1" = "help"
+//   "/" = ""    # removes a default binding
+//
+// Keys are single characters or key names, optionally with ctrl-, alt- and shift- in front.
+// Characters without ctrl or alt only work while the input line is empty. "quit" leaves the
+// calculator.
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    modes: Vec<String>,
+    undo_depth: Option<usize>,
+    colors: ColorsFile,
+    keys: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ColorsFile {
+    stack: Option<String>,
+    status_foreground: Option<String>,
+    status_background: Option<String>,
+    error_foreground: Option<String>,
+    error_background: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Colors {
+    pub stack: Color,
+    pub status_foreground: Color,
+    pub status_background: Color,
+    pub error_foreground: Color,
+    pub error_background: Color,
+}
+
+const DEFAULT_COLORS: Colors = Colors {
+    stack: Color::Reset,
+    status_foreground: Color::Black,
+    status_background: Color::White,
+    error_foreground: Color::Black,
+    error_background: Color::White,
+};
+
+const DEFAULT_KEYS: [(&str, &str); 6] = [
+    ("+", "add"),
+    ("-", "sub"),
+    ("*", "mul"),
+    ("/", "div"),
+    ("q", "quit"),
+    ("ctrl-c", "quit"),
+];
+
+// A key with its modifiers. Shift is ignored for characters, as it's part of the character.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    fn from_event(event: &KeyEvent) -> Key {
+        let mut modifiers = event.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT);
+        if !matches!(event.code, KeyCode::Char(_)) {
+            modifiers |= event.modifiers & KeyModifiers::SHIFT;
+        }
+        Key {
+            code: event.code,
+            modifiers,
+        }
+    }
+}
+
+struct Config {
+    colors: Colors,
+    keys: Vec<(Key, String)>,
+}
+
+lazy_static! {
+    static ref CONFIG: Mutex<Config> = Mutex::new(Config {
+        colors: DEFAULT_COLORS,
+        keys: DEFAULT_KEYS
+            .iter()
+            .map(|(key, command)| (parse_key(key).unwrap(), command.to_string()))
+            .collect(),
+    });
+}
+
+fn parse_key(text: &str) -> Result<Key, String> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut name = text;
+    // A single character is a key even if it's a dash.
+    while name.chars().count() > 1 {
+        if let Some(rest) = name.strip_prefix("ctrl-") {
+            modifiers |= KeyModifiers::CONTROL;
+            name = rest;
+        } else if let Some(rest) = name.strip_prefix("alt-") {
+            modifiers |= KeyModifiers::ALT;
+            name = rest;
+        } else if let Some(rest) = name.strip_prefix("shift-") {
+            modifiers |= KeyModifiers::SHIFT;
+            name = rest;
+        } else {
+            break;
+        }
+    }
+
+    let mut chars = name.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match name {
+            "space" => KeyCode::Char(' '),
+            "tab" => KeyCode::Tab,
+            "enter" => KeyCode::Enter,
+            "esc" => KeyCode::Esc,
+            "backspace" => KeyCode::Backspace,
+            "delete" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            _ => match name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                Some(n) if (1..=12).contains(&n) => KeyCode::F(n),
+                _ => return Err(format!("unknown key '{}'", text)),
+            },
+        },
+    };
+    if matches!(code, KeyCode::Char(_)) {
+        modifiers.remove(KeyModifiers::SHIFT);
+    }
+    Ok(Key { code, modifiers })
+}
+
+fn parse_color(name: &Option<String>, default: Color) -> Result<Color, String> {
+    match name {
+        Some(name) => name
+            .parse()
+            .map_err(|_| format!("unknown color '{}'", name)),
+        None => Ok(default),
+    }
+}
+
+fn parse_colors(colors: &ColorsFile) -> Result<Colors, String> {
+    Ok(Colors {
+        stack: parse_color(&colors.stack, DEFAULT_COLORS.stack)?,
+        status_foreground: parse_color(
+            &colors.status_foreground,
+            DEFAULT_COLORS.status_foreground,
+        )?,
+        status_background: parse_color(
+            &colors.status_background,
+            DEFAULT_COLORS.status_background,
+        )?,
+        error_foreground: parse_color(&colors.error_foreground, DEFAULT_COLORS.error_foreground)?,
+        error_background: parse_color(&colors.error_background, DEFAULT_COLORS.error_background)?,
+    })
+}
+
+// Bindings from the file replace the defaults for the same key, an empty command removes them.
+fn parse_keys(keys: &BTreeMap<String, String>) -> Result<Vec<(Key, String)>, String> {
+    let mut bindings = CONFIG.lock().unwrap().keys.clone();
+    for (text, command) in keys {
+        let key = parse_key(text)?;
+        bindings.retain(|(k, _)| *k != key);
+        if !command.is_empty() {
+            bindings.push((key, command.clone()));
+        }
+    }
+    Ok(bindings)
+}
+
+// Everything is checked before anything is applied, so a broken file changes nothing.
+fn apply(calc: &mut Calculator, text: &str) -> Result<(), String> {
+    let file: ConfigFile = toml::from_str(text).map_err(|e| e.message().trim().to_string())?;
+    let colors = parse_colors(&file.colors)?;
+    let keys = parse_keys(&file.keys)?;
+
+    let saved = calc.modes;
+    for command in &file.modes {
+        if !matches!(modes::set_mode(calc, command), CmdResult::Success) {
+            calc.modes = saved;
+            return Err(format!("invalid mode '{}'", command));
+        }
+    }
+    if let Some(depth) = file.undo_depth {
+        calc.history.set_depth(depth);
+    }
+
+    let mut config = CONFIG.lock().unwrap();
+    config.colors = colors;
+    config.keys = keys;
+    Ok(())
+}
+
+fn get_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("rpn").join("config.toml"))
+}
+
+// Reads the configuration file if there is one.
+pub fn load(calc: &mut Calculator) -> Result<(), String> {
+    let Some(path) = get_path() else {
+        return Ok(());
+    };
+    if !path.exists() {
+        return Ok(());
+    }
+    let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    apply(calc, &text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn get_colors() -> Colors {
+    CONFIG.lock().unwrap().colors
+}
+
+// The command bound to a key. While typing, only keys that can't be part of the input count.
+pub fn get_binding(event: &KeyEvent, typing: bool) -> Option<String> {
+    let key = Key::from_event(event);
+    if typing && matches!(key.code, KeyCode::Char(_)) && key.modifiers.is_empty() {
+        return None;
+    }
+    let config = CONFIG.lock().unwrap();
+    config
+        .keys
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, command)| command.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpn::session;
+
+    #[test]
+    fn test_keys() {
+        let key = |code, modifiers| Key { code, modifiers };
+        assert_eq!(
+            parse_key("-"),
+            Ok(key(KeyCode::Char('-'), KeyModifiers::NONE))
+        );
+        assert_eq!(
+            parse_key("ctrl--"),
+            Ok(key(KeyCode::Char('-'), KeyModifiers::CONTROL))
+        );
+        assert_eq!(
+            parse_key("ctrl-alt-z"),
+            Ok(key(
+                KeyCode::Char('z'),
+                KeyModifiers::CONTROL | KeyModifiers::ALT
+            ))
+        );
+        assert_eq!(
+            parse_key("shift-%"),
+            Ok(key(KeyCode::Char('%'), KeyModifiers::NONE))
+        );
+        assert_eq!(
+            parse_key("shift-f5"),
+            Ok(key(KeyCode::F(5), KeyModifiers::SHIFT))
+        );
+        assert_eq!(
+            parse_key("pageup"),
+            Ok(key(KeyCode::PageUp, KeyModifiers::NONE))
+        );
+        assert!(parse_key("f13").is_err());
+        assert!(parse_key("ctrl-").is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut calc = Calculator::new();
+        assert!(apply(&mut calc, "colours = 1").is_err());
+        assert!(apply(&mut calc, "[colors]\nstack = \"purple-ish\"").is_err());
+        assert!(apply(&mut calc, "[keys]\n\"hyper-x\" = \"add\"").is_err());
+        assert!(apply(&mut calc, "modes = [\"hex\", \"width 200\"]").is_err());
+        assert!(matches!(calc.modes.get_radix(), modes::Radix::Dec));
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut last = Calculator::new();
+        assert_eq!(last.exec("oct"), Ok(()));
+        let saved = session::to_text(&last);
+
+        let mut calc = Calculator::new();
+        assert_eq!(apply(&mut calc, "modes = [\"hex\", \"rad\"]"), Ok(()));
+        assert!(matches!(calc.modes.get_radix(), modes::Radix::Hex));
+
+        // The configured modes are only defaults, the restored session's modes win.
+        assert_eq!(session::from_text(&mut calc, &saved), Ok(()));
+        assert!(matches!(calc.modes.get_radix(), modes::Radix::Oct));
+        assert!(matches!(calc.modes.get_angle_mode(), modes::AngleMode::Deg));
+    }
+}
@@ -7,9 +7,9 @@ use ratatui::{Terminal, backend::CrosstermBackend};
 use std::io::{self, Stdout};
 use std::sync::Mutex;
 
-use crate::help;
-use crate::modes;
-use crate::stack;
+use crate::config;
+use crate::editor::Editor;
+use rpn::{Calculator, View, completion, help, registers, stack_manipulations};
 
 // All terminal handling is done here to later allow for different UIs.
 
@@ -36,13 +36,38 @@ pub fn restore() -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn draw(input: &str, error: &str) -> anyhow::Result<()> {
+// Lists completions side by side, as many on a line as fit in `width`.
+fn format_candidates(candidates: &[String], width: usize) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    for candidate in candidates {
+        let line = lines.last_mut().unwrap();
+        if !line.is_empty() && line.len() + 2 + candidate.len() > width {
+            lines.push(candidate.clone());
+        } else {
+            if !line.is_empty() {
+                line.push_str("  ");
+            }
+            line.push_str(candidate);
+        }
+    }
+    lines
+}
+
+// `candidates` are the completions to show instead of the stack, if any.
+pub fn draw(
+    calc: &Calculator,
+    editor: &Editor,
+    candidates: &[String],
+    error: &str,
+) -> anyhow::Result<()> {
     let mut terminal_lock = TERMINAL.lock().unwrap();
     let terminal = terminal_lock.as_mut().expect("Terminal is not setup");
 
+    let colors = config::get_colors();
     terminal.draw(|f| {
         use ratatui::{
             layout::{Constraint, Direction, Layout},
+            style::Stylize,
             style::{Color, Modifier, Style},
             text::{Line, Span},
             widgets::Paragraph,
@@ -61,35 +86,61 @@ pub fn draw(input: &str, error: &str) -> anyhow::Result<()> {
             .split(f.area());
 
         // Stack area
-        let mut stack_contents = stack::get_stack_contents();
-        // TODO: Crude. Clean up later.
-        if help::display_help() {
-            stack_contents = help::get_help();
-        }
+        let mut stack_contents = match calc.view {
+            View::Stack if !candidates.is_empty() => {
+                format_candidates(candidates, f.area().width as usize)
+            }
+            View::Stack => calc.get_stack_contents(f.area().width as usize),
+            View::Help => help::get_help(&calc.registry),
+            View::Variables => registers::get_vars(calc),
+            View::History => stack_manipulations::get_history(calc),
+        };
         while stack_contents.len() < f.area().height as usize - 2 {
             stack_contents.insert(0, "".to_string());
         }
-        let main_area = Paragraph::new(stack_contents.join("\n"));
+        let main_area = Paragraph::new(stack_contents.join("\n")).fg(colors.stack);
         f.render_widget(main_area, chunks[0]);
 
-        // Status area, inverted colors. Also used for error messages.
+        // Status area, inverted colors by default. Also used for error messages.
+        let (text, foreground, background) = if error.is_empty() {
+            (
+                calc.modes.get_modes_string(),
+                colors.status_foreground,
+                colors.status_background,
+            )
+        } else {
+            (
+                error.to_string(),
+                colors.error_foreground,
+                colors.error_background,
+            )
+        };
         let status = Paragraph::new(Line::from(Span::styled(
-            if error.is_empty() {
-                let modes = modes::get_modes_string();
-                modes.to_string()
-            } else {
-                error.to_string()
-            },
+            text,
             Style::default()
-                .fg(Color::Black)
-                .bg(Color::White)
+                .fg(foreground)
+                .bg(background)
                 .add_modifier(Modifier::BOLD),
         )));
         f.render_widget(status, chunks[1]);
 
-        // Input area
-        let input = Paragraph::new("> ".to_string() + input);
-        f.render_widget(input, chunks[2]);
+        // Input area, with a hint on how the command being typed is used. While searching the
+        // history, the search prompt replaces the usual one.
+        let (before, after) = editor.split_at_cursor();
+        let search_prompt = editor.get_search_prompt();
+        let prompt = search_prompt.as_deref().unwrap_or("> ");
+        let mut spans = vec![Span::raw(format!("{}{}{}", prompt, before, after))];
+        if search_prompt.is_none()
+            && let Some(hint) = completion::get_hint(calc, before)
+        {
+            spans.push(Span::styled(
+                format!("  {}", hint),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        f.render_widget(Paragraph::new(Line::from(spans)), chunks[2]);
+        let column = (prompt.chars().count() + before.chars().count()) as u16;
+        f.set_cursor_position((chunks[2].x + column, chunks[2].y));
     })?;
 
     Ok(())
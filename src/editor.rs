@@ -0,0 +1,373 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::fs;
+use std::path::PathBuf;
+
+use rpn::session;
+
+// The input line of the terminal frontend, with readline style editing:
+//   Left/Right, Ctrl-B/F        move by character
+//   Ctrl-Left/Right, Alt-B/F    move by word
+//   Home/End, Ctrl-A/E          move to the start or end
+//   Backspace/Delete            delete a character
+//   Ctrl-W                      delete the word before the cursor
+//   Ctrl-U/K                    delete to the start or end
+//   Up/Down                     go through the lines entered before
+//   Ctrl-R                      search the lines entered before, again for older matches
+// Words are separated by spaces, like the tokens of the input. The entered lines are kept in
+// rpn/history in the XDG state directory.
+
+// Older lines are dropped from the history file.
+const MAX_HISTORY: usize = 1000;
+
+// An incremental reverse search, started with Ctrl-R.
+struct Search {
+    query: String,
+    // The history entry shown, None if nothing matches.
+    found: Option<usize>,
+    // The line to go back to if the search is cancelled.
+    text: String,
+    cursor: usize,
+}
+
+#[derive(Default)]
+pub struct Editor {
+    text: String,
+    // Byte position in text, always on a character boundary.
+    cursor: usize,
+    history: Vec<String>,
+    // The history entry being edited with Up and Down, and the new line it replaced.
+    browsing: Option<usize>,
+    new_line: String,
+    search: Option<Search>,
+}
+
+impl Editor {
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+
+    // The input before and after the cursor.
+    pub fn split_at_cursor(&self) -> (&str, &str) {
+        self.text.split_at(self.cursor)
+    }
+
+    // Whether keys are input rather than bound commands, see config::get_binding().
+    pub fn is_typing(&self) -> bool {
+        !self.text.is_empty() || self.search.is_some()
+    }
+
+    // Replaces the input before the cursor, e.g. with a completion.
+    pub fn set_before_cursor(&mut self, text: &str) {
+        self.text.replace_range(..self.cursor, text);
+        self.cursor = text.len();
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+        self.browsing = None;
+    }
+
+    fn set_text(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.cursor = self.text.len();
+    }
+
+    fn previous_boundary(&self, position: usize) -> usize {
+        self.text[..position]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn next_boundary(&self, position: usize) -> usize {
+        self.text[position..]
+            .chars()
+            .next()
+            .map_or(position, |c| position + c.len_utf8())
+    }
+
+    // The start of the word before the cursor, skipping spaces in between.
+    fn word_start(&self) -> usize {
+        let before = self.text[..self.cursor].trim_end();
+        before.rfind(' ').map_or(0, |i| i + 1)
+    }
+
+    // The end of the word after the cursor, skipping spaces in between.
+    fn word_end(&self) -> usize {
+        let after = &self.text[self.cursor..];
+        let start = after.len() - after.trim_start().len();
+        after[start..]
+            .find(' ')
+            .map_or(self.text.len(), |i| self.cursor + start + i)
+    }
+
+    fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    fn delete(&mut self, start: usize, end: usize) {
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+    }
+
+    // Adds an entered line to the history, unless it's the same as the last one.
+    pub fn add_history(&mut self, line: &str) {
+        if !line.trim().is_empty() && self.history.last().is_none_or(|last| last != line) {
+            self.history.push(line.to_string());
+        }
+        self.browsing = None;
+    }
+
+    fn history_previous(&mut self) {
+        let index = match self.browsing {
+            None if self.history.is_empty() => return,
+            None => {
+                self.new_line = self.text.clone();
+                self.history.len() - 1
+            }
+            Some(index) => index.saturating_sub(1),
+        };
+        self.browsing = Some(index);
+        self.set_text(&self.history[index].clone());
+    }
+
+    fn history_next(&mut self) {
+        match self.browsing {
+            Some(index) if index + 1 < self.history.len() => {
+                self.browsing = Some(index + 1);
+                self.set_text(&self.history[index + 1].clone());
+            }
+            Some(_) => {
+                self.browsing = None;
+                self.set_text(&self.new_line.clone());
+            }
+            None => {}
+        }
+    }
+
+    // Finds the newest entry containing the query, starting at `before` and going back.
+    fn find(&mut self, before: usize) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        search.found = self.history[..before]
+            .iter()
+            .rposition(|line| line.contains(&search.query));
+        if let Some(index) = search.found {
+            let line = &self.history[index];
+            self.cursor = line.find(&search.query).unwrap_or(0);
+            self.text = line.clone();
+        }
+    }
+
+    // The prompt shown instead of "> " while searching.
+    pub fn get_search_prompt(&self) -> Option<String> {
+        self.search.as_ref().map(|search| {
+            let failed = if search.found.is_none() && !search.query.is_empty() {
+                "failed "
+            } else {
+                ""
+            };
+            format!("({}reverse-i-search)'{}': ", failed, search.query)
+        })
+    }
+
+    // Keys while searching. Returns false for keys that end the search and are then handled as
+    // usual, like Enter and the movement keys.
+    fn search_key(&mut self, key: &KeyEvent) -> bool {
+        let Some(search) = &mut self.search else {
+            return false;
+        };
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        let newest = self.history.len();
+        let before = match key.code {
+            KeyCode::Char('r') if control => search.found.unwrap_or(newest),
+            KeyCode::Char('g') if control => {
+                self.cancel_search();
+                return true;
+            }
+            KeyCode::Esc => {
+                self.cancel_search();
+                return true;
+            }
+            KeyCode::Char(c) if !control && !key.modifiers.contains(KeyModifiers::ALT) => {
+                search.query.push(c);
+                // The current match stays if it still matches.
+                search.found.map_or(newest, |i| i + 1)
+            }
+            KeyCode::Backspace => {
+                search.query.pop();
+                newest
+            }
+            _ => {
+                self.search = None;
+                return false;
+            }
+        };
+        self.find(before);
+        true
+    }
+
+    fn cancel_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            self.text = search.text;
+            self.cursor = search.cursor;
+        }
+    }
+
+    // Handles an editing key. Returns false if the key isn't one, e.g. Enter or Tab.
+    pub fn handle_key(&mut self, key: &KeyEvent) -> bool {
+        if self.search.is_some() && self.search_key(key) {
+            return true;
+        }
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        match key.code {
+            KeyCode::Char('a') if control => self.cursor = 0,
+            KeyCode::Char('e') if control => self.cursor = self.text.len(),
+            KeyCode::Char('b') if control => self.cursor = self.previous_boundary(self.cursor),
+            KeyCode::Char('f') if control => self.cursor = self.next_boundary(self.cursor),
+            KeyCode::Char('b') if alt => self.cursor = self.word_start(),
+            KeyCode::Char('f') if alt => self.cursor = self.word_end(),
+            KeyCode::Char('w') if control => self.delete(self.word_start(), self.cursor),
+            KeyCode::Char('u') if control => self.delete(0, self.cursor),
+            KeyCode::Char('k') if control => self.delete(self.cursor, self.text.len()),
+            KeyCode::Char('r') if control => {
+                self.search = Some(Search {
+                    query: String::new(),
+                    found: None,
+                    text: self.text.clone(),
+                    cursor: self.cursor,
+                });
+            }
+            KeyCode::Char(c) if !control && !alt => self.insert(c),
+            KeyCode::Left if control => self.cursor = self.word_start(),
+            KeyCode::Right if control => self.cursor = self.word_end(),
+            KeyCode::Left => self.cursor = self.previous_boundary(self.cursor),
+            KeyCode::Right => self.cursor = self.next_boundary(self.cursor),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.text.len(),
+            KeyCode::Backspace => self.delete(self.previous_boundary(self.cursor), self.cursor),
+            KeyCode::Delete => self.delete(self.cursor, self.next_boundary(self.cursor)),
+            KeyCode::Up => self.history_previous(),
+            KeyCode::Down => self.history_next(),
+            KeyCode::Esc => self.clear(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn get_history_path() -> Option<PathBuf> {
+        Some(session::get_directory()?.join("history"))
+    }
+
+    // Reads the lines entered in earlier sessions. A missing or unreadable file is an empty
+    // history, blank lines are skipped like when they are entered.
+    pub fn load_history(&mut self) {
+        if let Some(path) = Editor::get_history_path()
+            && let Ok(text) = fs::read_to_string(path)
+        {
+            self.history = text
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+    }
+
+    pub fn save_history(&self) -> Result<(), String> {
+        let path = Editor::get_history_path().ok_or("No state directory")?;
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(|e| format!("{}: {}", directory.display(), e))?;
+        }
+        let start = self.history.len().saturating_sub(MAX_HISTORY);
+        let text: String = self.history[start..]
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect();
+        fs::write(&path, text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(editor: &mut Editor, code: KeyCode, modifiers: KeyModifiers) {
+        assert!(editor.handle_key(&KeyEvent::new(code, modifiers)));
+    }
+
+    fn type_text(editor: &mut Editor, text: &str) {
+        for c in text.chars() {
+            press(editor, KeyCode::Char(c), KeyModifiers::NONE);
+        }
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut editor = Editor::default();
+        type_text(&mut editor, "1 2 ädd");
+        press(&mut editor, KeyCode::Left, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Backspace, KeyModifiers::NONE);
+        assert_eq!(editor.split_at_cursor(), ("1 2 ä", "d"));
+        press(&mut editor, KeyCode::Left, KeyModifiers::CONTROL);
+        assert_eq!(editor.split_at_cursor(), ("1 2 ", "äd"));
+        press(&mut editor, KeyCode::Char('w'), KeyModifiers::CONTROL);
+        assert_eq!(editor.split_at_cursor(), ("1 ", "äd"));
+        press(&mut editor, KeyCode::Char('f'), KeyModifiers::ALT);
+        assert_eq!(editor.split_at_cursor(), ("1 äd", ""));
+        press(&mut editor, KeyCode::Home, KeyModifiers::NONE);
+        type_text(&mut editor, "3 ");
+        press(&mut editor, KeyCode::Char('k'), KeyModifiers::CONTROL);
+        assert_eq!(editor.get_text(), "3 ");
+        press(&mut editor, KeyCode::Char('u'), KeyModifiers::CONTROL);
+        assert!(editor.get_text().is_empty());
+        assert!(!editor.handle_key(&KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE)));
+    }
+
+    #[test]
+    fn test_history() {
+        let mut editor = Editor::default();
+        for line in ["1 2 add", "3 sto x", "3 sto x", "4 mul"] {
+            editor.add_history(line);
+        }
+        type_text(&mut editor, "new");
+        press(&mut editor, KeyCode::Up, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Up, KeyModifiers::NONE);
+        assert_eq!(editor.get_text(), "3 sto x");
+        press(&mut editor, KeyCode::Up, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Up, KeyModifiers::NONE);
+        assert_eq!(editor.get_text(), "1 2 add");
+        press(&mut editor, KeyCode::Down, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Down, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Down, KeyModifiers::NONE);
+        assert_eq!(editor.get_text(), "new");
+    }
+
+    #[test]
+    fn test_search() {
+        let mut editor = Editor::default();
+        for line in ["1 2 add", "3 sto x", "5 add"] {
+            editor.add_history(line);
+        }
+        type_text(&mut editor, "typed");
+        press(&mut editor, KeyCode::Char('r'), KeyModifiers::CONTROL);
+        type_text(&mut editor, "ad");
+        assert_eq!(editor.get_text(), "5 add");
+        press(&mut editor, KeyCode::Char('r'), KeyModifiers::CONTROL);
+        assert_eq!(editor.split_at_cursor(), ("1 2 ", "add"));
+        type_text(&mut editor, "x");
+        assert!(editor.get_search_prompt().unwrap().starts_with("(failed"));
+        press(&mut editor, KeyCode::Esc, KeyModifiers::NONE);
+        assert_eq!(editor.get_text(), "typed");
+        assert!(editor.get_search_prompt().is_none());
+
+        press(&mut editor, KeyCode::Char('r'), KeyModifiers::CONTROL);
+        type_text(&mut editor, "sto");
+        press(&mut editor, KeyCode::End, KeyModifiers::NONE);
+        assert!(editor.get_search_prompt().is_none());
+        assert_eq!(editor.split_at_cursor(), ("3 sto x", ""));
+    }
+}
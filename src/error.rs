@@ -0,0 +1,134 @@
+use crate::stack::AcceptedTypes;
+use std::fmt;
+
+// Why a command failed. Frontends show errors with Display, tests and the JSON output match on
+// the variants to get the details.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    // The command needs more values than there are on the stack.
+    StackUnderflow {
+        expected: usize,
+        actual: usize,
+    },
+    // A value the command works on has a type it doesn't accept, e.g. a fraction for "and".
+    TypeMismatch {
+        expected: AcceptedTypes,
+        found: &'static str,
+    },
+    // The values are outside of what the operation is defined for, e.g. division by zero.
+    Domain(&'static str),
+    // The result doesn't fit, e.g. a fraction in overflow_error mode.
+    Overflow(&'static str),
+    // Missing, extra or invalid arguments, given inline or taken from the stack.
+    Argument(&'static str),
+    // The command can't be done right now, e.g. undo with nothing to undo.
+    Failed(&'static str),
+    UnknownCommand {
+        name: String,
+        suggestions: Vec<String>,
+    },
+    InvalidNumber {
+        token: String,
+        reason: String,
+    },
+    // A file couldn't be read or written.
+    File {
+        path: String,
+        reason: String,
+    },
+    // A line of a script failed.
+    Script {
+        path: String,
+        line: usize,
+        error: Box<CommandError>,
+    },
+}
+
+// An error together with the input that caused it, as returned by Calculator::exec.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommandError {
+    pub command: String,
+    pub error: Error,
+}
+
+// The kind of values a command accepts, in words.
+pub fn describe_types(types: AcceptedTypes) -> &'static str {
+    if types.contains(AcceptedTypes::FLOAT) {
+        "numbers"
+    } else if types.contains(AcceptedTypes::FRACTIONS) {
+        "exact numbers"
+    } else {
+        "integers"
+    }
+}
+
+fn with_article(noun: &str) -> String {
+    if noun.starts_with(['a', 'e', 'i', 'o', 'u']) {
+        format!("an {}", noun)
+    } else {
+        format!("a {}", noun)
+    }
+}
+
+impl Error {
+    // Errors that already say which input they are about, so it isn't repeated in front.
+    fn names_input(&self) -> bool {
+        matches!(
+            self,
+            Error::UnknownCommand { .. }
+                | Error::InvalidNumber { .. }
+                | Error::File { .. }
+                | Error::Script { .. }
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::StackUnderflow { expected, actual } => write!(
+                f,
+                "Not enough values on stack, needs {} but has {}",
+                expected, actual
+            ),
+            Error::TypeMismatch { expected, found } => write!(
+                f,
+                "Expected {}, found {}",
+                describe_types(*expected),
+                with_article(found)
+            ),
+            Error::Domain(message)
+            | Error::Overflow(message)
+            | Error::Argument(message)
+            | Error::Failed(message) => write!(f, "{}", message),
+            Error::UnknownCommand { name, suggestions } => {
+                write!(f, "Unknown command '{}'", name)?;
+                let quoted: Vec<String> = suggestions.iter().map(|s| format!("'{}'", s)).collect();
+                match quoted.split_last() {
+                    Some((last, [])) => write!(f, " — did you mean {}?", last),
+                    Some((last, rest)) => {
+                        write!(f, " — did you mean {} or {}?", rest.join(", "), last)
+                    }
+                    None => Ok(()),
+                }
+            }
+            Error::InvalidNumber { token, reason } => {
+                write!(f, "Invalid number '{}': {}", token, reason)
+            }
+            Error::File { path, reason } => write!(f, "{}: {}", path, reason),
+            Error::Script { path, line, error } => write!(f, "{}:{}: {}", path, line, error),
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.error.names_input() {
+            write!(f, "{}", self.error)
+        } else {
+            write!(f, "{}: {}", self.command, self.error)
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
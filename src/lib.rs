@@ -0,0 +1,27 @@
+// The calculator engine. All state lives in a Calculator, so several can be used side by side;
+// the terminal and command line frontends in main.rs only handle input and output.
+
+pub mod basic_math;
+pub mod bit_fields;
+mod calculator;
+pub mod command;
+pub mod completion;
+pub mod error;
+pub mod help;
+pub mod literals;
+pub mod logic_operators;
+pub mod modes;
+pub mod registers;
+pub mod scientific;
+pub mod script;
+pub mod session;
+pub mod stack;
+pub mod stack_manipulations;
+
+pub use calculator::{Calculator, View};
+pub use error::{CommandError, Error};
+
+pub enum CmdResult {
+    Success,
+    Error(Error),
+}
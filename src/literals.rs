@@ -0,0 +1,233 @@
+use crate::stack::StackValue;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::Zero;
+
+// Parsing of number literals. Supported formats:
+//   42, -1_000_000           decimal integers of any size, `_` separates digits
+//   0x1f, 0o17, 0b1010       hexadecimal, octal and binary, also the older h1f, o17, b1010
+//   3/4, 1_1/2, 1_000/3      fractions and mixed numbers, a mixed number's numerator is
+//                            smaller than its denominator
+//   4k7, 10u, 2.2M           SI prefixes, the prefix can replace the decimal point
+//   1.5, 1e-3                floats
+//   'A'                      character codes
+
+const SI_PREFIXES: [(char, i32); 10] = [
+    ('f', -15),
+    ('p', -12),
+    ('n', -9),
+    ('u', -6),
+    ('m', -3),
+    ('k', 3),
+    ('M', 6),
+    ('G', 9),
+    ('T', 12),
+    ('P', 15),
+];
+
+fn radix_name(radix: u32) -> &'static str {
+    match radix {
+        2 => "binary",
+        8 => "octal",
+        16 => "hexadecimal",
+        _ => "decimal",
+    }
+}
+
+fn parse_digits(digits: &str, radix: u32) -> Result<BigInt, String> {
+    if let Some(c) = digits.chars().find(|c| *c != '_' && !c.is_digit(radix)) {
+        return Err(format!("invalid {} digit '{}'", radix_name(radix), c));
+    }
+    let digits: String = digits.chars().filter(|c| *c != '_').collect();
+    if digits.is_empty() {
+        return Err("missing digits".to_string());
+    }
+    Ok(BigInt::parse_bytes(digits.as_bytes(), radix).unwrap())
+}
+
+fn is_digits(digits: &str, radix: u32) -> bool {
+    !digits.is_empty() && digits.chars().all(|c| c == '_' || c.is_digit(radix))
+}
+
+// Parses a plain decimal number with an optional fraction part, e.g. 4.7, exactly.
+fn parse_decimal(number: &str) -> Result<BigRational, String> {
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let mut value = BigRational::from_integer(parse_digits(whole, 10)?);
+    if !fraction.is_empty() {
+        let digits = fraction.chars().filter(|c| *c != '_').count();
+        let scale = BigInt::from(10).pow(digits as u32);
+        value += BigRational::new(parse_digits(fraction, 10)?, scale);
+    }
+    Ok(value)
+}
+
+fn parse_fraction(token: &str) -> Result<BigRational, String> {
+    let (numerator, denominator) = token.split_once('/').unwrap();
+    let denominator = parse_digits(denominator, 10)?;
+    if denominator.is_zero() {
+        return Err("denominator is zero".to_string());
+    }
+    // In a mixed number the last `_` separates the whole part from the numerator, which is
+    // smaller than the denominator. Otherwise `_` separates digits, as in 1_000/3.
+    if let Some((whole, part)) = numerator.rsplit_once('_')
+        && is_digits(part, 10)
+        && (part == "0" || !part.starts_with('0'))
+    {
+        let part = parse_digits(part, 10)?;
+        if part < denominator {
+            let whole = BigRational::from_integer(parse_digits(whole, 10)?);
+            return Ok(whole + BigRational::new(part, denominator));
+        }
+    }
+    Ok(BigRational::new(parse_digits(numerator, 10)?, denominator))
+}
+
+// Returns None if the token doesn't use an SI prefix.
+fn parse_si(token: &str) -> Option<Result<BigRational, String>> {
+    let (position, prefix) = token.char_indices().find(|(_, c)| c.is_alphabetic())?;
+    let (_, exponent) = SI_PREFIXES.iter().find(|(p, _)| *p == prefix)?;
+    let (mantissa, decimals) = (&token[..position], &token[position + 1..]);
+    if !is_digits(decimals, 10) && !decimals.is_empty() {
+        return None;
+    }
+
+    let mantissa = if decimals.is_empty() {
+        parse_decimal(mantissa)
+    } else if mantissa.contains('.') {
+        return Some(Err(format!(
+            "decimals both before and after the prefix '{}'",
+            prefix
+        )));
+    } else {
+        parse_decimal(&format!("{}.{}", mantissa, decimals))
+    };
+
+    let scale = BigRational::from_integer(BigInt::from(10)).pow(*exponent);
+    Some(mantissa.map(|m| m * scale))
+}
+
+fn parse_char(token: &str) -> Result<StackValue, String> {
+    let mut chars = token[1..token.len() - 1].chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(StackValue::Integer(c as i128)),
+        _ => Err("character literals hold exactly one character".to_string()),
+    }
+}
+
+fn exact(value: BigRational) -> StackValue {
+    StackValue::Big(value)
+}
+
+fn parse_unsigned(token: &str) -> Result<StackValue, String> {
+    for (prefix, radix) in [("0x", 16), ("0o", 8), ("0b", 2)] {
+        if let Some(digits) = token.strip_prefix(prefix) {
+            return parse_digits(digits, radix).map(|i| exact(BigRational::from_integer(i)));
+        }
+    }
+
+    if token.contains('/') {
+        return parse_fraction(token).map(exact);
+    }
+
+    if is_digits(token, 10) {
+        return parse_digits(token, 10).map(|i| exact(BigRational::from_integer(i)));
+    }
+
+    if let Some(result) = parse_si(token) {
+        return result.map(exact);
+    }
+
+    let float: String = token.chars().filter(|c| *c != '_').collect();
+    match float.parse::<f64>() {
+        Ok(f) if f.is_finite() => Ok(StackValue::Float(f)),
+        Ok(_) => Err("out of the range of floats".to_string()),
+        Err(_) => Err("not a valid number".to_string()),
+    }
+}
+
+fn negate(value: StackValue) -> StackValue {
+    match value {
+        StackValue::Big(b) => StackValue::Big(-b),
+        StackValue::Float(f) => StackValue::Float(-f),
+        other => other,
+    }
+}
+
+// Returns None if the token doesn't look like a number, otherwise the value or why it's invalid.
+pub fn parse(token: &str) -> Option<Result<StackValue, String>> {
+    if token.len() >= 2 && token.starts_with('\'') && token.ends_with('\'') {
+        return Some(parse_char(token));
+    }
+
+    // The older prefixes are only numbers if all digits are valid, "bin" is a command.
+    for (prefix, radix) in [('h', 16), ('o', 8), ('b', 2)] {
+        if let Some(digits) = token.strip_prefix(prefix)
+            && is_digits(digits, radix)
+        {
+            return Some(parse_digits(digits, radix).map(|i| exact(BigRational::from_integer(i))));
+        }
+    }
+
+    let (negative, unsigned) = match token.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, token.strip_prefix('+').unwrap_or(token)),
+    };
+
+    if !unsigned.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+
+    let value = parse_unsigned(unsigned);
+    Some(if negative { value.map(negate) } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(num: i64, denom: i64) -> Option<Result<StackValue, String>> {
+        Some(Ok(StackValue::Big(BigRational::new(
+            BigInt::from(num),
+            BigInt::from(denom),
+        ))))
+    }
+
+    #[test]
+    fn test_integers() {
+        assert_eq!(parse("1_000"), big(1000, 1));
+        assert_eq!(parse("-0x1F"), big(-31, 1));
+        assert_eq!(parse("0o17"), big(15, 1));
+        assert_eq!(parse("b1010"), big(10, 1));
+        assert_eq!(parse("0xdead_beef"), big(0xdead_beef, 1));
+        assert_eq!(parse("'A'"), Some(Ok(StackValue::Integer(65))));
+        assert_eq!(parse("bin"), None);
+        assert_eq!(
+            parse("0x1g"),
+            Some(Err("invalid hexadecimal digit 'g'".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_fractions() {
+        assert_eq!(parse("3/4"), big(3, 4));
+        assert_eq!(parse("-1_1/2"), big(-3, 2));
+        assert_eq!(parse("1_000_1/2"), big(2001, 2));
+        assert_eq!(parse("1_000/3"), big(1000, 3));
+        assert_eq!(parse("10_000/7"), big(10000, 7));
+        assert_eq!(parse("1_5/4"), big(15, 4));
+        assert_eq!(parse("1/0"), Some(Err("denominator is zero".to_string())));
+    }
+
+    #[test]
+    fn test_si() {
+        assert_eq!(parse("4k7"), big(4700, 1));
+        assert_eq!(parse("10u"), big(1, 100_000));
+        assert_eq!(parse("2.2M"), big(2_200_000, 1));
+        assert_eq!(parse("1.5"), Some(Ok(StackValue::Float(1.5))));
+        assert_eq!(parse("1x"), Some(Err("not a valid number".to_string())));
+        assert_eq!(
+            parse("1e400"),
+            Some(Err("out of the range of floats".to_string()))
+        );
+    }
+}
@@ -1,109 +1,272 @@
 use crate::CmdResult;
-use crate::help;
+use crate::calculator::Calculator;
+use crate::command::{Builtin, Category};
+use crate::error::Error;
+use crate::modes::Modes;
 use crate::stack;
+use num_bigint::BigInt;
+use num_rational::BigRational;
 
 // Logic areas only work on integers, which is a special case of fractions. See stack.rs for more info.
+// Integers too large for i128 are handled as big rationals with a denominator of one.
+//
+// All operations work on words of the current binary width. Operands are truncated to the word
+// size (setting the overflow flag if they didn't fit) and results are interpreted according to
+// the current binary mode.
 
-fn and() -> CmdResult {
-    if let Some(values) = stack::get(2, stack::AcceptedTypes::INTEGERS) {
-        let (a, b) = match (&values[0], &values[1]) {
-            (stack::StackValue::Integer(a), stack::StackValue::Integer(b)) => (*a, *b),
-            _ => return CmdResult::Error("AND operation requires two integer values"),
+pub const INTEGER_TYPES: stack::AcceptedTypes =
+    stack::AcceptedTypes::INTEGERS.union(stack::AcceptedTypes::BIG);
+
+// Fetches `count` integers from the stack as words of the current width.
+pub fn get_words(calc: &mut Calculator, count: usize) -> Result<Vec<u128>, Error> {
+    let values = calc
+        .stack
+        .get(count, INTEGER_TYPES)
+        .ok_or_else(|| calc.stack.get_error(count, INTEGER_TYPES))?;
+    let (min, max) = calc.modes.get_word_range();
+    let mut overflow = false;
+    let mut words = Vec::with_capacity(count);
+    for value in &values {
+        let value = match value {
+            stack::StackValue::Integer(a) => BigInt::from(*a),
+            stack::StackValue::Big(a) if a.is_integer() => a.to_integer(),
+            _ => {
+                return Err(Error::TypeMismatch {
+                    expected: INTEGER_TYPES,
+                    found: value.get_type_name(),
+                });
+            }
         };
+        overflow |= value < min || value > max;
+        words.push(calc.modes.to_word(&value));
+    }
+    calc.modes.set_overflow(overflow);
+    Ok(words)
+}
 
-        let result = a & b;
+pub fn push_word(calc: &mut Calculator, count: usize, word: u128) -> CmdResult {
+    calc.stack.drop(count);
+    let value = calc.modes.from_word(word);
+    calc.stack
+        .push(stack::StackValue::Big(BigRational::from_integer(value)));
+    CmdResult::Success
+}
 
-        stack::drop(2);
-        stack::push(stack::StackValue::Integer(result));
+// Replaces the top `count` values with an integer result, wrapped to the word size.
+pub fn push_integer(calc: &mut Calculator, count: usize, value: &BigInt) -> CmdResult {
+    let (min, max) = calc.modes.get_word_range();
+    calc.modes.set_overflow(*value < min || *value > max);
+    let word = calc.modes.to_word(value);
+    push_word(calc, count, word)
+}
 
-        CmdResult::Success
-    } else {
-        CmdResult::Error("Not enough values on stack")
+fn unary(calc: &mut Calculator, operation: fn(u128) -> u128) -> CmdResult {
+    match get_words(calc, 1) {
+        Ok(words) => push_word(calc, 1, operation(words[0])),
+        Err(e) => CmdResult::Error(e),
     }
 }
 
-fn not() -> CmdResult {
-    if let Some(values) = stack::get(1, stack::AcceptedTypes::INTEGERS) {
-        let a = match &values[0] {
-            stack::StackValue::Integer(a) => *a,
-            _ => return CmdResult::Error("NOT operation requires an integer value"),
-        };
-
-        let result = !a;
+fn binary(calc: &mut Calculator, operation: fn(u128, u128) -> u128) -> CmdResult {
+    match get_words(calc, 2) {
+        Ok(words) => push_word(calc, 2, operation(words[0], words[1])),
+        Err(e) => CmdResult::Error(e),
+    }
+}
 
-        stack::drop(1);
-        stack::push(stack::StackValue::Integer(result));
+// Shifts and rotates take the word from Y and the number of steps from X. The steps are done one
+// bit at a time to get the carry flag right, each step returning the new word and carry.
+fn shift(calc: &mut Calculator, step: fn(u128, bool, &Modes) -> (u128, bool)) -> CmdResult {
+    let words = match get_words(calc, 2) {
+        Ok(words) => words,
+        Err(e) => return CmdResult::Error(e),
+    };
+    let steps = match calc.stack.get(1, stack::AcceptedTypes::INTEGERS) {
+        Some(values) => match values[0] {
+            stack::StackValue::Integer(n) if n >= 0 => n,
+            _ => {
+                return CmdResult::Error(Error::Argument(
+                    "Shift count must be a non-negative integer",
+                ));
+            }
+        },
+        None => {
+            return CmdResult::Error(Error::Argument(
+                "Shift count must be a non-negative integer",
+            ));
+        }
+    };
 
-        CmdResult::Success
+    // Rotates repeat after `bits` steps, rotates through carry after `bits + 1` and shifts
+    // don't change after that, so anything beyond a common cycle is just repetition.
+    let bits = calc.modes.get_binary_width().bits() as i128;
+    let cycle = bits * (bits + 1);
+    let steps = if steps > cycle {
+        steps % cycle + cycle
     } else {
-        CmdResult::Error("Not enough values on stack")
+        steps
+    };
+
+    let mut word = words[0];
+    let mut carry = calc.modes.get_carry();
+    for _ in 0..steps {
+        (word, carry) = step(word, carry, &calc.modes);
     }
+    calc.modes.set_carry(carry);
+    push_word(calc, 2, word)
 }
 
-fn or() -> CmdResult {
-    if let Some(values) = stack::get(2, stack::AcceptedTypes::INTEGERS) {
-        let (a, b) = match (&values[0], &values[1]) {
-            (stack::StackValue::Integer(a), stack::StackValue::Integer(b)) => (*a, *b),
-            _ => return CmdResult::Error("OR operation requires two integer values"),
-        };
-
-        let result = a | b;
-
-        stack::drop(2);
-        stack::push(stack::StackValue::Integer(result));
+fn shl(word: u128, _: bool, modes: &Modes) -> (u128, bool) {
+    (
+        (word << 1) & modes.get_word_mask(),
+        word & modes.get_sign_bit() != 0,
+    )
+}
 
-        CmdResult::Success
-    } else {
-        CmdResult::Error("Not enough values on stack")
-    }
+fn shr(word: u128, _: bool, _: &Modes) -> (u128, bool) {
+    (word >> 1, word & 1 != 0)
 }
 
-fn xor() -> CmdResult {
-    if let Some(values) = stack::get(2, stack::AcceptedTypes::INTEGERS) {
-        let (a, b) = match (&values[0], &values[1]) {
-            (stack::StackValue::Integer(a), stack::StackValue::Integer(b)) => (*a, *b),
-            _ => return CmdResult::Error("XOR operation requires two integer values"),
-        };
+fn sar(word: u128, _: bool, modes: &Modes) -> (u128, bool) {
+    ((word >> 1) | (word & modes.get_sign_bit()), word & 1 != 0)
+}
 
-        let result = a ^ b;
+fn rol(word: u128, _: bool, modes: &Modes) -> (u128, bool) {
+    let out = word & modes.get_sign_bit() != 0;
+    (((word << 1) & modes.get_word_mask()) | out as u128, out)
+}
 
-        stack::drop(2);
-        stack::push(stack::StackValue::Integer(result));
+fn ror(word: u128, _: bool, modes: &Modes) -> (u128, bool) {
+    let out = word & 1 != 0;
+    (
+        (word >> 1) | if out { modes.get_sign_bit() } else { 0 },
+        out,
+    )
+}
 
-        CmdResult::Success
-    } else {
-        CmdResult::Error("Not enough values on stack")
-    }
+fn rolc(word: u128, carry: bool, modes: &Modes) -> (u128, bool) {
+    (
+        ((word << 1) & modes.get_word_mask()) | carry as u128,
+        word & modes.get_sign_bit() != 0,
+    )
 }
 
-pub fn commands(cmd: &str) -> CmdResult {
-    match cmd {
-        "and" => and(),
-        "or" => or(),
-        "not" => not(),
-        "xor" => xor(),
-        _ => CmdResult::NoMatch,
-    }
+fn rorc(word: u128, carry: bool, modes: &Modes) -> (u128, bool) {
+    (
+        (word >> 1) | if carry { modes.get_sign_bit() } else { 0 },
+        word & 1 != 0,
+    )
 }
 
-pub const HELP: help::Category = help::Category {
-    category: "Logic Operators",
+pub const COMMANDS: Category = Category {
+    name: "Logic Operators",
     commands: &[
-        help::Cmd {
-            command: "and",
-            help: "Performs a bitwise AND operation on the top two integers on the stack.",
-        },
-        help::Cmd {
-            command: "or",
-            help: "Performs a bitwise OR operation on the top two integers on the stack.",
-        },
-        help::Cmd {
-            command: "not",
-            help: "Performs a bitwise NOT operation on the top integer on the stack.",
-        },
-        help::Cmd {
-            command: "xor",
-            help: "Performs a bitwise XOR operation on the top two integers on the stack.",
-        },
+        Builtin::new(
+            "and",
+            "Performs a bitwise AND operation on the top two integers on the stack.",
+            |calc, _| binary(calc, |a, b| a & b),
+        )
+        .with_operands(2, INTEGER_TYPES),
+        Builtin::new(
+            "or",
+            "Performs a bitwise OR operation on the top two integers on the stack.",
+            |calc, _| binary(calc, |a, b| a | b),
+        )
+        .with_operands(2, INTEGER_TYPES),
+        Builtin::new(
+            "not",
+            "Performs a bitwise NOT operation on the top integer on the stack.",
+            |calc, _| unary(calc, |a| !a),
+        )
+        .with_operands(1, INTEGER_TYPES),
+        Builtin::new(
+            "xor",
+            "Performs a bitwise XOR operation on the top two integers on the stack.",
+            |calc, _| binary(calc, |a, b| a ^ b),
+        )
+        .with_operands(2, INTEGER_TYPES),
+        Builtin::new(
+            "nand",
+            "Performs a bitwise NAND operation on the top two integers on the stack.",
+            |calc, _| binary(calc, |a, b| !(a & b)),
+        )
+        .with_operands(2, INTEGER_TYPES),
+        Builtin::new(
+            "nor",
+            "Performs a bitwise NOR operation on the top two integers on the stack.",
+            |calc, _| binary(calc, |a, b| !(a | b)),
+        )
+        .with_operands(2, INTEGER_TYPES),
+        Builtin::new(
+            "xnor",
+            "Performs a bitwise XNOR operation on the top two integers on the stack.",
+            |calc, _| binary(calc, |a, b| !(a ^ b)),
+        )
+        .with_operands(2, INTEGER_TYPES),
+        Builtin::new(
+            "shl",
+            "Shifts the second integer left by the top integer, carry gets the last bit out.",
+            |calc, _| shift(calc, shl),
+        )
+        .with_operands(2, INTEGER_TYPES),
+        Builtin::new(
+            "shr",
+            "Logical shift right of the second integer by the top integer.",
+            |calc, _| shift(calc, shr),
+        )
+        .with_operands(2, INTEGER_TYPES),
+        Builtin::new(
+            "sar",
+            "Arithmetic shift right of the second integer by the top integer, keeping the sign bit.",
+            |calc, _| shift(calc, sar),
+        )
+        .with_operands(2, INTEGER_TYPES),
+        Builtin::new(
+            "rol",
+            "Rotates the second integer left by the top integer.",
+            |calc, _| shift(calc, rol),
+        )
+        .with_operands(2, INTEGER_TYPES),
+        Builtin::new(
+            "ror",
+            "Rotates the second integer right by the top integer.",
+            |calc, _| shift(calc, ror),
+        )
+        .with_operands(2, INTEGER_TYPES),
+        Builtin::new(
+            "rolc",
+            "Rotates the second integer left through the carry flag by the top integer.",
+            |calc, _| shift(calc, rolc),
+        )
+        .with_operands(2, INTEGER_TYPES),
+        Builtin::new(
+            "rorc",
+            "Rotates the second integer right through the carry flag by the top integer.",
+            |calc, _| shift(calc, rorc),
+        )
+        .with_operands(2, INTEGER_TYPES),
     ],
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate() {
+        // The default 32 bit unsigned mode.
+        let modes = Modes::default();
+        assert_eq!(rol(0x8000_0001, false, &modes), (0x0000_0003, true));
+        assert_eq!(ror(0x0000_0001, false, &modes), (0x8000_0000, true));
+        assert_eq!(rolc(0x8000_0000, false, &modes), (0x0000_0000, true));
+        assert_eq!(rorc(0x0000_0000, true, &modes), (0x8000_0000, false));
+    }
+
+    #[test]
+    fn test_shift() {
+        let modes = Modes::default();
+        assert_eq!(shl(0xc000_0000, false, &modes), (0x8000_0000, true));
+        assert_eq!(shr(0x8000_0001, false, &modes), (0x4000_0000, true));
+        assert_eq!(sar(0x8000_0000, false, &modes), (0xc000_0000, false));
+    }
+}
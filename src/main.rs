@@ -1,128 +1,68 @@
 use crossterm::event::{self, Event, KeyCode};
 use std::env;
+use std::io::{self, BufRead, IsTerminal};
+use std::path::Path;
+use std::process;
 
-mod basic_math;
-mod display;
-mod help;
-mod logic_operators;
-mod modes;
-mod stack;
-mod stack_manipulations;
-
-pub enum CmdResult {
-    Success,
-    NoMatch,
-    Error(&'static str),
-}
-
-const COMMANDS_FUNCTIONS: [fn(&str) -> CmdResult; 5] = [
-    basic_math::commands,
-    logic_operators::commands,
-    modes::commands,
-    stack_manipulations::commands,
-    help::commands,
-];
-
-fn parse_input(input: &mut String) -> Result<(), String> {
-    let trimmed = input.split_whitespace().collect::<Vec<_>>().join(" ");
-
-    for commands in COMMANDS_FUNCTIONS.iter() {
-        match commands(&trimmed.as_str()) {
-            CmdResult::Success => {
-                input.clear();
-                return Ok(());
-            }
-            CmdResult::Error(e) => {
-                return Err(e.to_string());
-            }
-            CmdResult::NoMatch => {
-                // Continue processing
-            }
-        }
-    }
-
-    if trimmed.starts_with("h") {
-        // Hexadecimal
-        let hex_str = &trimmed[1..];
-        if let Ok(value) = i128::from_str_radix(hex_str, 16) {
-            stack::push(stack::StackValue::Fraction((value, 1)));
-            input.clear();
-            return Ok(());
-        }
-    }
+use rpn::command::Registry;
+use rpn::{Calculator, CommandError, View, completion, help, script, session};
 
-    if trimmed.starts_with("b") {
-        // Binary
-        let bin_str = &trimmed[1..];
-        if let Ok(value) = i128::from_str_radix(bin_str, 2) {
-            stack::push(stack::StackValue::Fraction((value, 1)));
-            input.clear();
-            return Ok(());
-        }
-    }
-
-    if let Ok(value) = trimmed.parse::<i128>() {
-        stack::push(stack::StackValue::Fraction((value, 1)));
-        input.clear();
-        return Ok(());
-    }
-
-    if let Ok(value) = trimmed.parse::<f64>() {
-        stack::push(stack::StackValue::Float(value));
-        input.clear();
-        return Ok(());
-    }
+mod config;
+mod display;
+mod editor;
+mod output;
 
-    Err("Invalid input format".to_string())
-}
+use editor::Editor;
 
-fn main_loop() -> anyhow::Result<()> {
-    let mut input_buffer = String::new();
-    let mut error_message = String::new();
+// Starts with `error_message` shown in the status line.
+fn main_loop(
+    calc: &mut Calculator,
+    editor: &mut Editor,
+    mut error_message: String,
+) -> anyhow::Result<()> {
+    // Shown instead of the stack after Tab, when there is more than one completion.
+    let mut candidates = Vec::new();
 
     loop {
-        display::draw(&input_buffer, &error_message).expect("Failed to draw UI");
+        display::draw(calc, editor, &candidates, &error_message).expect("Failed to draw UI");
 
         if event::poll(std::time::Duration::from_millis(100))? {
             error_message.clear();
-            help::clear_help();
+            candidates.clear();
+            calc.view = View::Stack;
             if let Event::Key(key) = event::read().unwrap() {
-                if input_buffer.len() == 0 {
-                    // If input buffer is empty, check for shortcut keys first.
-                    let result = match key.code {
-                        KeyCode::Char('q') => return Ok(()),
-                        KeyCode::Char(c) => basic_math::quick_commands(&c),
-                        _ => CmdResult::NoMatch,
-                    };
-
-                    match result {
-                        CmdResult::Success => {
-                            continue;
-                        }
-                        CmdResult::Error(e) => {
-                            error_message = e.to_string();
-                        }
-                        CmdResult::NoMatch => {
-                            // Continue to normal input processing
-                        }
+                // Key bindings come first, see config.rs.
+                if let Some(command) = config::get_binding(&key, editor.is_typing()) {
+                    if command == "quit" {
+                        return Ok(());
+                    }
+                    if let Err(e) = calc.exec(&command) {
+                        error_message = e.to_string();
                     }
+                    continue;
+                }
+                if editor.handle_key(&key) {
+                    continue;
                 }
                 match key.code {
-                    KeyCode::Char(c) => {
-                        input_buffer.push(c);
-                    }
-                    KeyCode::Backspace => {
-                        input_buffer.pop();
-                    }
-                    KeyCode::Esc => {
-                        input_buffer.clear();
+                    KeyCode::Tab => {
+                        let (before, _) = editor.split_at_cursor();
+                        let (completed, completions) = completion::complete(calc, before);
+                        editor.set_before_cursor(&completed);
+                        if completions.len() > 1 {
+                            candidates = completions;
+                        }
                     }
-                    KeyCode::Enter => match parse_input(&mut input_buffer) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error_message = e;
+                    KeyCode::Enter => {
+                        let line = editor.get_text().to_string();
+                        editor.add_history(&line);
+                        match calc.exec(&line) {
+                            Ok(_) => editor.clear(),
+                            Err(e) => {
+                                error_message = e.to_string();
+                            }
                         }
-                    },
+                    }
                     _ => {}
                 }
             }
@@ -130,35 +70,150 @@ fn main_loop() -> anyhow::Result<()> {
     }
 }
 
+// Command line arguments are input lines or script files given with -f. Consecutive arguments
+// form a single line, so commands can take inline arguments like in the terminal frontend.
+enum Input {
+    Line(String),
+    Script(String),
+}
+
+// Reads input lines from stdin and prints the result after each line, keeping the stack between
+// lines. Returns false if any line failed.
+fn batch(calc: &mut Calculator, format: output::Format, full_stack: bool) -> bool {
+    let mut success = true;
+    for (number, line) in io::stdin().lock().lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Error reading input: {}", e);
+                return false;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match calc.exec(&line) {
+            Ok(_) => output::print(calc, format, full_stack),
+            Err(e) => {
+                output::print_error(&format!("Error on line {}", number + 1), &e, format);
+                success = false;
+            }
+        }
+    }
+    success
+}
+
 fn main() {
     let args: Vec<String> = env::args().skip(1).collect();
 
-    // If there are command line arguments, process them and exit.
-    if args.len() > 0 {
-        // Special case for help command.
-        if args[0] == "help" {
-            help::print_help();
-            return;
-        }
+    // Special case for help command.
+    if args.first().is_some_and(|arg| arg == "help") {
+        help::print_help(&Registry::default());
+        return;
+    }
 
-        for arg in args {
-            match parse_input(&mut arg.clone()) {
-                Ok(_) => {}
-                Err(e) => {
-                    eprintln!("Error parsing '{}': {}", arg, e);
-                    break;
+    // The configuration sets the defaults, everything else can override them.
+    let mut calc = Calculator::new();
+    let config_error = config::load(&mut calc)
+        .err()
+        .map(|e| format!("Config not loaded: {}", e));
+
+    let mut format = output::Format::Plain;
+    let mut full_stack = false;
+    let mut batch_mode = false;
+    let mut fresh = false;
+    let mut inputs = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--stack" {
+            full_stack = true;
+        } else if arg == "--batch" {
+            batch_mode = true;
+        } else if arg == "--fresh" {
+            fresh = true;
+        } else if arg == "-f" {
+            match args.next() {
+                Some(file) => inputs.push(Input::Script(file)),
+                None => {
+                    eprintln!("-f needs a script file");
+                    process::exit(2);
+                }
+            }
+        } else if arg == "--format" || arg.starts_with("--format=") {
+            let name = match arg.strip_prefix("--format=") {
+                Some(name) => Some(name.to_string()),
+                None => args.next(),
+            };
+            match name.as_deref().and_then(output::Format::from_name) {
+                Some(f) => format = f,
+                None => {
+                    eprintln!("Invalid format, expected plain, hex or json");
+                    process::exit(2);
                 }
             }
+        } else if let Some(Input::Line(line)) = inputs.last_mut() {
+            line.push(' ');
+            line.push_str(&arg);
+        } else {
+            inputs.push(Input::Line(arg));
+        }
+    }
+
+    let interactive = inputs.is_empty() && !batch_mode && io::stdin().is_terminal();
+    if let Some(e) = &config_error
+        && !interactive
+    {
+        eprintln!("{}", e);
+    }
+
+    // Arguments are processed first, also in batch mode.
+    for input in &inputs {
+        let result = match input {
+            Input::Line(arg) => calc
+                .exec(arg)
+                .map_err(|e| (format!("Error parsing '{}'", arg), e)),
+            Input::Script(file) => script::run(&mut calc, Path::new(file)).map_err(|error| {
+                let error = CommandError {
+                    command: file.clone(),
+                    error,
+                };
+                ("Error".to_string(), error)
+            }),
+        };
+        if let Err((context, e)) = result {
+            output::print_error(&context, &e, format);
+            process::exit(1);
+        }
+    }
+
+    // Without arguments, input from a pipe or file is processed in batch mode.
+    if batch_mode || (inputs.is_empty() && !io::stdin().is_terminal()) {
+        if !batch(&mut calc, format, full_stack) {
+            process::exit(1);
         }
+    } else if !inputs.is_empty() {
+        output::print(&calc, format, full_stack);
     } else {
-        // Enter interactive mode.
+        // Enter interactive mode, continuing the last session.
+        let mut message = config_error.unwrap_or_default();
+        if !fresh && let Err(e) = session::load_session(&mut calc) {
+            message = format!("Session not restored: {}", e);
+        }
+        let mut editor = Editor::default();
+        editor.load_history();
         display::setup().expect("Failed to setup terminal");
-        let err = main_loop();
+        let err = main_loop(&mut calc, &mut editor, message);
         display::restore().expect("Failed to restore terminal");
+        if let Err(e) = editor.save_history() {
+            eprintln!("Input history not saved: {}", e);
+        }
         match err {
             Ok(_) => {
+                if let Err(e) = session::save_session(&calc) {
+                    eprintln!("Session not saved: {}", e);
+                }
                 // Show the resulting stack contents on exit.
-                stack::get_stack_contents()
+                calc.get_stack_contents(usize::MAX)
                     .iter()
                     .for_each(|line| println!("{}", line));
             }
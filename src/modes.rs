@@ -1,128 +1,588 @@
 use crate::CmdResult;
-use crate::help;
-use std::sync::Mutex;
+use crate::calculator::Calculator;
+use crate::command::{self, Builtin, Category};
+use crate::error::Error;
+use crate::stack;
+use bitflags::bitflags;
+use num_bigint::BigInt;
+use num_traits::{One, Signed, ToPrimitive, Zero};
 
 #[derive(Clone, Copy)]
 pub enum BinaryMode {
     Signed,
     Unsigned,
+    OnesComplement,
+    SignMagnitude,
 }
 
+// Word size in bits, 1 to 128.
 #[derive(Clone, Copy)]
-pub enum BinaryWidth {
-    W8,
-    W16,
-    W32,
-    W64,
+pub struct BinaryWidth(u32);
+
+impl BinaryWidth {
+    pub fn bits(self) -> u32 {
+        self.0
+    }
 }
 
-static BINARY_MODE: Mutex<BinaryMode> = Mutex::new(BinaryMode::Unsigned);
-static BINARY_WIDTH: Mutex<BinaryWidth> = Mutex::new(BinaryWidth::W32);
+// What to do when exact fraction arithmetic no longer fits.
+#[derive(Clone, Copy)]
+pub enum OverflowMode {
+    Exact,
+    Promote,
+    Error,
+}
 
-fn set_binary_mode_signed() -> CmdResult {
-    let mut mode = BINARY_MODE.lock().unwrap();
-    *mode = BinaryMode::Signed;
-    CmdResult::Success
+#[derive(Clone, Copy)]
+pub enum AngleMode {
+    Deg,
+    Rad,
+    Grad,
 }
 
-fn set_binary_mode_unsigned() -> CmdResult {
-    let mut mode = BINARY_MODE.lock().unwrap();
-    *mode = BinaryMode::Unsigned;
-    CmdResult::Success
+// How floats and the decimal value of fractions are shown, with the number of digits after the
+// decimal point.
+#[derive(Clone, Copy)]
+pub enum DisplayFormat {
+    Fix(usize),
+    Sci(usize),
+    Eng(usize),
+    // Engineering notation using SI prefixes, e.g. 4.7k.
+    Si(usize),
+    All,
 }
 
-fn set_binary_width(width: BinaryWidth) -> CmdResult {
-    let mut w = BINARY_WIDTH.lock().unwrap();
-    *w = width;
-    CmdResult::Success
+const MAX_DIGITS: u32 = 20;
+
+// Radix used for the primary rendering of integers.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Radix {
+    Hex,
+    Dec,
+    Oct,
+    Bin,
 }
 
-fn set_binary_width_8() -> CmdResult {
-    set_binary_width(BinaryWidth::W8)
+bitflags! {
+    // Secondary representations shown after integers.
+    #[derive(Clone, Copy)]
+    pub struct Representations: u8 {
+        const HEX = 0b0001;
+        const DEC = 0b0010;
+        const OCT = 0b0100;
+        const BIN = 0b1000;
+    }
 }
 
-fn set_binary_width_16() -> CmdResult {
-    set_binary_width(BinaryWidth::W16)
+// The modes and status flags of a calculator.
+#[derive(Clone, Copy)]
+pub struct Modes {
+    binary_mode: BinaryMode,
+    binary_width: BinaryWidth,
+    overflow_mode: OverflowMode,
+    angle_mode: AngleMode,
+    display_format: DisplayFormat,
+    radix: Radix,
+    representations: Representations,
+    // In integer mode arithmetic on integers wraps at the word size like on the HP-16C.
+    integer_mode: bool,
+    // Status flags set by the logic operators, shown in the status line like on the HP-16C.
+    carry: bool,
+    overflow: bool,
+}
+
+impl Default for Modes {
+    fn default() -> Modes {
+        Modes {
+            binary_mode: BinaryMode::Unsigned,
+            binary_width: BinaryWidth(32),
+            overflow_mode: OverflowMode::Exact,
+            angle_mode: AngleMode::Deg,
+            display_format: DisplayFormat::Fix(6),
+            radix: Radix::Dec,
+            representations: Representations::HEX.union(Representations::BIN),
+            integer_mode: false,
+            carry: false,
+            overflow: false,
+        }
+    }
 }
 
-fn set_binary_width_32() -> CmdResult {
-    set_binary_width(BinaryWidth::W32)
+fn set_binary_width(calc: &mut Calculator, bits: u32) -> CmdResult {
+    if !(1..=128).contains(&bits) {
+        return CmdResult::Error(Error::Argument(
+            "Binary width must be between 1 and 128 bits",
+        ));
+    }
+    calc.modes.binary_width = BinaryWidth(bits);
+    CmdResult::Success
 }
 
-fn set_binary_width_64() -> CmdResult {
-    set_binary_width(BinaryWidth::W64)
+// Numeric arguments are given inline (`width 12`) or taken from the stack.
+pub fn get_argument(
+    calc: &mut Calculator,
+    args: &[&str],
+    range: std::ops::RangeInclusive<u32>,
+    error: &'static str,
+) -> Result<u32, Error> {
+    match args {
+        [arg] => match arg.parse::<u32>() {
+            Ok(n) if range.contains(&n) => Ok(n),
+            _ => Err(Error::Argument(error)),
+        },
+        [] => match calc.stack.get(1, stack::AcceptedTypes::INTEGERS) {
+            Some(values) => match values[0] {
+                stack::StackValue::Integer(n) => match u32::try_from(n) {
+                    Ok(n) if range.contains(&n) => {
+                        calc.stack.drop(1);
+                        Ok(n)
+                    }
+                    _ => Err(Error::Argument(error)),
+                },
+                _ => Err(Error::Argument(error)),
+            },
+            None => Err(calc.stack.get_error(1, stack::AcceptedTypes::INTEGERS)),
+        },
+        _ => Err(Error::Argument("Wrong number of arguments")),
+    }
 }
 
-pub fn get_binary_mode() -> BinaryMode {
-    let mode = BINARY_MODE.lock().unwrap();
-    *mode
+fn set_binary_width_from(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    match get_argument(
+        calc,
+        args,
+        1..=128,
+        "Binary width must be between 1 and 128 bits",
+    ) {
+        Ok(bits) => set_binary_width(calc, bits),
+        Err(e) => CmdResult::Error(e),
+    }
 }
 
-pub fn get_binary_width() -> BinaryWidth {
-    let width = BINARY_WIDTH.lock().unwrap();
-    *width
+fn set_display_format(
+    calc: &mut Calculator,
+    args: &[&str],
+    format: fn(usize) -> DisplayFormat,
+) -> CmdResult {
+    match get_argument(
+        calc,
+        args,
+        0..=MAX_DIGITS,
+        "Number of digits must be between 0 and 20",
+    ) {
+        Ok(digits) => {
+            calc.modes.display_format = format(digits as usize);
+            CmdResult::Success
+        }
+        Err(e) => CmdResult::Error(e),
+    }
 }
 
-pub fn get_modes_string() -> String {
-    let mode = BINARY_MODE.lock().unwrap();
-    let width = BINARY_WIDTH.lock().unwrap();
-    let mut modes = String::new();
+impl Modes {
+    pub fn get_binary_mode(&self) -> BinaryMode {
+        self.binary_mode
+    }
+
+    pub fn get_binary_width(&self) -> BinaryWidth {
+        self.binary_width
+    }
 
-    modes += match *width {
-        BinaryWidth::W8 => "8",
-        BinaryWidth::W16 => "16",
-        BinaryWidth::W32 => "32",
-        BinaryWidth::W64 => "64",
-    };
+    pub fn get_overflow_mode(&self) -> OverflowMode {
+        self.overflow_mode
+    }
 
-    modes += "bit ";
+    pub fn get_angle_mode(&self) -> AngleMode {
+        self.angle_mode
+    }
 
-    modes += match *mode {
-        BinaryMode::Signed => "Signed",
-        BinaryMode::Unsigned => "Unsigned",
-    };
+    pub fn get_integer_mode(&self) -> bool {
+        self.integer_mode
+    }
 
-    modes
-}
+    pub fn set_carry(&mut self, carry: bool) {
+        self.carry = carry;
+    }
+
+    pub fn get_carry(&self) -> bool {
+        self.carry
+    }
+
+    pub fn set_overflow(&mut self, overflow: bool) {
+        self.overflow = overflow;
+    }
+
+    pub fn get_overflow(&self) -> bool {
+        self.overflow
+    }
+
+    // The range of values that fit in a word of the current width and binary mode.
+    pub fn get_word_range(&self) -> (BigInt, BigInt) {
+        let bits = self.binary_width.bits();
+        let half = BigInt::one() << (bits - 1);
+        match self.binary_mode {
+            BinaryMode::Signed => (-half.clone(), half - 1),
+            BinaryMode::Unsigned => (BigInt::zero(), (BigInt::one() << bits) - 1),
+            BinaryMode::OnesComplement | BinaryMode::SignMagnitude => {
+                (BigInt::one() - half.clone(), half - 1)
+            }
+        }
+    }
+
+    pub fn get_word_mask(&self) -> u128 {
+        u128::MAX >> (128 - self.binary_width.bits())
+    }
 
-pub fn commands(cmd: &str) -> CmdResult {
-    match cmd {
-        "signed" => set_binary_mode_signed(),
-        "unsigned" => set_binary_mode_unsigned(),
-        "width8" => set_binary_width_8(),
-        "width16" => set_binary_width_16(),
-        "width32" => set_binary_width_32(),
-        "width64" => set_binary_width_64(),
-        _ => CmdResult::NoMatch,
+    pub fn get_sign_bit(&self) -> u128 {
+        1 << (self.binary_width.bits() - 1)
     }
+
+    // Encodes a value as a word of the current width and binary mode. Values that don't fit are
+    // truncated.
+    pub fn to_word(&self, value: &BigInt) -> u128 {
+        let magnitude = (value.magnitude() & num_bigint::BigUint::from(u128::MAX))
+            .to_u128()
+            .unwrap();
+        let word = match self.binary_mode {
+            BinaryMode::Signed | BinaryMode::Unsigned => {
+                if value.is_negative() {
+                    magnitude.wrapping_neg()
+                } else {
+                    magnitude
+                }
+            }
+            BinaryMode::OnesComplement => {
+                if value.is_negative() {
+                    !magnitude
+                } else {
+                    magnitude
+                }
+            }
+            BinaryMode::SignMagnitude => {
+                if value.is_negative() {
+                    (magnitude & !self.get_sign_bit()) | self.get_sign_bit()
+                } else {
+                    magnitude & !self.get_sign_bit()
+                }
+            }
+        };
+        word & self.get_word_mask()
+    }
+
+    // Decodes a word of the current width and binary mode.
+    pub fn from_word(&self, word: u128) -> BigInt {
+        let mask = self.get_word_mask();
+        let sign_bit = self.get_sign_bit();
+        let word = word & mask;
+        let negative = word & sign_bit != 0;
+        match self.binary_mode {
+            BinaryMode::Unsigned => BigInt::from(word),
+            BinaryMode::Signed if negative => -BigInt::from(word.wrapping_neg() & mask),
+            BinaryMode::OnesComplement if negative => -BigInt::from(!word & mask),
+            BinaryMode::SignMagnitude if negative => -BigInt::from(word & !sign_bit),
+            _ => BigInt::from(word),
+        }
+    }
+
+    pub fn get_radix(&self) -> Radix {
+        self.radix
+    }
+
+    pub fn get_representations(&self) -> Representations {
+        self.representations
+    }
+
+    pub fn get_display_format(&self) -> DisplayFormat {
+        self.display_format
+    }
+
+    pub fn get_modes_string(&self) -> String {
+        let mut modes = String::new();
+
+        modes += &self.binary_width.bits().to_string();
+
+        modes += "bit ";
+
+        modes += match self.binary_mode {
+            BinaryMode::Signed => "Signed",
+            BinaryMode::Unsigned => "Unsigned",
+            BinaryMode::OnesComplement => "OnesComp",
+            BinaryMode::SignMagnitude => "SignMag",
+        };
+
+        if self.integer_mode {
+            modes += " INT";
+        }
+
+        modes += match self.overflow_mode {
+            OverflowMode::Exact => " Ovf:Exact",
+            OverflowMode::Promote => " Ovf:Float",
+            OverflowMode::Error => " Ovf:Error",
+        };
+
+        modes += match self.angle_mode {
+            AngleMode::Deg => " DEG",
+            AngleMode::Rad => " RAD",
+            AngleMode::Grad => " GRAD",
+        };
+
+        modes += &match self.display_format {
+            DisplayFormat::Fix(digits) => format!(" FIX{}", digits),
+            DisplayFormat::Sci(digits) => format!(" SCI{}", digits),
+            DisplayFormat::Eng(digits) => format!(" ENG{}", digits),
+            DisplayFormat::Si(digits) => format!(" SI{}", digits),
+            DisplayFormat::All => " ALL".to_string(),
+        };
+
+        modes += match self.radix {
+            Radix::Hex => " HEX",
+            Radix::Dec => " DEC",
+            Radix::Oct => " OCT",
+            Radix::Bin => " BIN",
+        };
+
+        if self.carry {
+            modes += " CARRY";
+        }
+
+        if self.overflow {
+            modes += " OVERFLOW";
+        }
+
+        modes
+    }
+
+    // Restores the modes a new calculator starts with. The status flags are kept.
+    pub fn reset(&mut self) {
+        *self = Modes {
+            carry: self.carry,
+            overflow: self.overflow,
+            ..Modes::default()
+        };
+    }
+
+    // The commands that set the current modes after a reset(), used to save them in a readable
+    // way.
+    pub fn get_mode_commands(&self) -> Vec<String> {
+        let mut commands: Vec<String> = vec![
+            match self.binary_mode {
+                BinaryMode::Signed => "signed",
+                BinaryMode::Unsigned => "unsigned",
+                BinaryMode::OnesComplement => "ones_complement",
+                BinaryMode::SignMagnitude => "sign_magnitude",
+            }
+            .to_string(),
+            format!("width {}", self.binary_width.bits()),
+            if self.integer_mode {
+                "integer_mode"
+            } else {
+                "fraction_mode"
+            }
+            .to_string(),
+            match self.overflow_mode {
+                OverflowMode::Exact => "overflow_exact",
+                OverflowMode::Promote => "overflow_float",
+                OverflowMode::Error => "overflow_error",
+            }
+            .to_string(),
+            match self.angle_mode {
+                AngleMode::Deg => "deg",
+                AngleMode::Rad => "rad",
+                AngleMode::Grad => "grad",
+            }
+            .to_string(),
+            match self.display_format {
+                DisplayFormat::Fix(digits) => format!("fix {}", digits),
+                DisplayFormat::Sci(digits) => format!("sci {}", digits),
+                DisplayFormat::Eng(digits) => format!("eng {}", digits),
+                DisplayFormat::Si(digits) => format!("si {}", digits),
+                DisplayFormat::All => "all".to_string(),
+            },
+            match self.radix {
+                Radix::Hex => "hex",
+                Radix::Dec => "dec",
+                Radix::Oct => "oct",
+                Radix::Bin => "bin",
+            }
+            .to_string(),
+        ];
+
+        // The representations are toggles, so only the differences from the default are listed.
+        let changed = self
+            .representations
+            .symmetric_difference(Representations::HEX | Representations::BIN);
+        for (flag, command) in [
+            (Representations::HEX, "show_hex"),
+            (Representations::DEC, "show_dec"),
+            (Representations::OCT, "show_oct"),
+            (Representations::BIN, "show_bin"),
+        ] {
+            if changed.contains(flag) {
+                commands.push(command.to_string());
+            }
+        }
+        commands
+    }
+}
+
+// Sets a mode, for commands without arguments.
+fn set(calc: &mut Calculator, change: impl FnOnce(&mut Modes)) -> CmdResult {
+    change(&mut calc.modes);
+    CmdResult::Success
 }
 
-pub const HELP: help::Category = help::Category {
-    category: "Modes",
+pub const COMMANDS: Category = Category {
+    name: "Modes",
     commands: &[
-        help::Cmd {
-            command: "signed",
-            help: "Set binary mode to signed",
-        },
-        help::Cmd {
-            command: "unsigned",
-            help: "Set binary mode to unsigned",
-        },
-        help::Cmd {
-            command: "width8",
-            help: "Set binary width to 8 bits",
-        },
-        help::Cmd {
-            command: "width16",
-            help: "Set binary width to 16 bits",
-        },
-        help::Cmd {
-            command: "width32",
-            help: "Set binary width to 32 bits",
-        },
-        help::Cmd {
-            command: "width64",
-            help: "Set binary width to 64 bits",
-        },
+        Builtin::new("signed", "Set binary mode to signed", |calc, _| {
+            set(calc, |m| m.binary_mode = BinaryMode::Signed)
+        }),
+        Builtin::new("unsigned", "Set binary mode to unsigned", |calc, _| {
+            set(calc, |m| m.binary_mode = BinaryMode::Unsigned)
+        }),
+        Builtin::new(
+            "ones_complement",
+            "Set binary mode to one's complement",
+            |calc, _| set(calc, |m| m.binary_mode = BinaryMode::OnesComplement),
+        ),
+        Builtin::new(
+            "sign_magnitude",
+            "Set binary mode to sign-magnitude",
+            |calc, _| set(calc, |m| m.binary_mode = BinaryMode::SignMagnitude),
+        ),
+        Builtin::new(
+            "integer_mode",
+            "Integer arithmetic wraps at the binary width, division truncates",
+            |calc, _| set(calc, |m| m.integer_mode = true),
+        ),
+        Builtin::new(
+            "fraction_mode",
+            "Integer arithmetic gives exact fractions (default)",
+            |calc, _| set(calc, |m| m.integer_mode = false),
+        ),
+        Builtin::new(
+            "width",
+            "Set binary width to n bits, 1 to 128",
+            set_binary_width_from,
+        )
+        .with_arguments(&["n"]),
+        Builtin::new("width8", "Set binary width to 8 bits", |calc, _| {
+            set_binary_width(calc, 8)
+        }),
+        Builtin::new("width16", "Set binary width to 16 bits", |calc, _| {
+            set_binary_width(calc, 16)
+        }),
+        Builtin::new("width32", "Set binary width to 32 bits", |calc, _| {
+            set_binary_width(calc, 32)
+        }),
+        Builtin::new("width64", "Set binary width to 64 bits", |calc, _| {
+            set_binary_width(calc, 64)
+        }),
+        Builtin::new(
+            "overflow_exact",
+            "On fraction overflow, switch to arbitrary precision",
+            |calc, _| set(calc, |m| m.overflow_mode = OverflowMode::Exact),
+        ),
+        Builtin::new(
+            "overflow_float",
+            "On fraction overflow, push an inexact float result",
+            |calc, _| set(calc, |m| m.overflow_mode = OverflowMode::Promote),
+        ),
+        Builtin::new(
+            "overflow_error",
+            "On fraction overflow, report an error and leave the stack untouched",
+            |calc, _| set(calc, |m| m.overflow_mode = OverflowMode::Error),
+        ),
+        Builtin::new("deg", "Set angle mode to degrees", |calc, _| {
+            set(calc, |m| m.angle_mode = AngleMode::Deg)
+        }),
+        Builtin::new("rad", "Set angle mode to radians", |calc, _| {
+            set(calc, |m| m.angle_mode = AngleMode::Rad)
+        }),
+        Builtin::new("grad", "Set angle mode to gradians", |calc, _| {
+            set(calc, |m| m.angle_mode = AngleMode::Grad)
+        }),
+        Builtin::new("fix", "Show numbers with n decimals", |calc, args| {
+            set_display_format(calc, args, DisplayFormat::Fix)
+        })
+        .with_arguments(&["n"]),
+        Builtin::new(
+            "sci",
+            "Show numbers in scientific notation with n decimals",
+            |calc, args| set_display_format(calc, args, DisplayFormat::Sci),
+        )
+        .with_arguments(&["n"]),
+        Builtin::new(
+            "eng",
+            "Show numbers in engineering notation with n decimals",
+            |calc, args| set_display_format(calc, args, DisplayFormat::Eng),
+        )
+        .with_arguments(&["n"]),
+        Builtin::new(
+            "si",
+            "Show numbers in engineering notation with SI prefixes and up to n decimals",
+            |calc, args| set_display_format(calc, args, DisplayFormat::Si),
+        )
+        .with_arguments(&["n"]),
+        Builtin::new(
+            "all",
+            "Show numbers with all significant decimals",
+            |calc, _| set(calc, |m| m.display_format = DisplayFormat::All),
+        ),
+        Builtin::new("hex", "Show integers in hexadecimal", |calc, _| {
+            set(calc, |m| m.radix = Radix::Hex)
+        }),
+        Builtin::new("dec", "Show integers in decimal", |calc, _| {
+            set(calc, |m| m.radix = Radix::Dec)
+        }),
+        Builtin::new("oct", "Show integers in octal", |calc, _| {
+            set(calc, |m| m.radix = Radix::Oct)
+        }),
+        Builtin::new("bin", "Show integers in binary", |calc, _| {
+            set(calc, |m| m.radix = Radix::Bin)
+        }),
+        Builtin::new(
+            "show_hex",
+            "Toggle showing the hexadecimal value after integers",
+            |calc, _| set(calc, |m| m.representations.toggle(Representations::HEX)),
+        ),
+        Builtin::new(
+            "show_dec",
+            "Toggle showing the decimal value after integers",
+            |calc, _| set(calc, |m| m.representations.toggle(Representations::DEC)),
+        ),
+        Builtin::new(
+            "show_oct",
+            "Toggle showing the octal value after integers",
+            |calc, _| set(calc, |m| m.representations.toggle(Representations::OCT)),
+        ),
+        Builtin::new(
+            "show_bin",
+            "Toggle showing the binary value after integers",
+            |calc, _| set(calc, |m| m.representations.toggle(Representations::BIN)),
+        ),
+        Builtin::new(
+            "clear_flags",
+            "Clear the carry and overflow flags",
+            |calc, _| {
+                set(calc, |m| {
+                    m.carry = false;
+                    m.overflow = false;
+                })
+            },
+        ),
     ],
 };
+
+// Runs a mode command like "hex" or "width 16". Config and session files set their modes with
+// this, so they can't run any other commands.
+pub fn set_mode(calc: &mut Calculator, command: &str) -> CmdResult {
+    let args: Vec<&str> = command.split_whitespace().collect();
+    match COMMANDS
+        .commands
+        .iter()
+        .find(|c| args.first() == Some(&c.name))
+    {
+        Some(builtin) => command::run(builtin, calc, &args[1..]),
+        None => CmdResult::Error(Error::Argument("Unknown mode")),
+    }
+}
@@ -0,0 +1,244 @@
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+use rpn::Calculator;
+use rpn::error::{self, CommandError, Error};
+use rpn::stack;
+use rpn::stack::StackValue;
+
+// Output of the resulting stack in command line mode, meant for shell scripts.
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Plain,
+    Hex,
+    Json,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "plain" => Some(Format::Plain),
+            "hex" => Some(Format::Hex),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+fn exact(value: &StackValue) -> Option<BigRational> {
+    match value {
+        StackValue::Integer(i) => Some(stack::to_big((*i, 1))),
+        StackValue::Fraction(frac) => Some(stack::to_big(*frac)),
+        StackValue::Big(big) => Some(big.clone()),
+        StackValue::Float(_) | StackValue::Inexact(_) => None,
+    }
+}
+
+// Integers that fit in the current word as plain hex, e.g. for C headers. Anything else is shown
+// as in plain format.
+fn format_hex(calc: &Calculator, value: &StackValue) -> String {
+    if let Some(exact) = exact(value)
+        && exact.is_integer()
+    {
+        let (min, max) = calc.modes.get_word_range();
+        if *exact.numer() >= min && *exact.numer() <= max {
+            let digits = calc.modes.get_binary_width().bits().div_ceil(4) as usize;
+            return format!("0x{:0digits$x}", calc.modes.to_word(exact.numer()));
+        }
+    }
+    stack::format_value(value, false, &calc.modes)
+}
+
+fn json_float(f: f64) -> String {
+    if f.is_finite() {
+        format!("{:?}", f)
+    } else {
+        "null".to_string()
+    }
+}
+
+// Integers are JSON numbers of any size, fractions an object with the exact parts as well as the
+// approximate value.
+fn format_json(value: &StackValue) -> String {
+    match exact(value) {
+        Some(exact) if exact.is_integer() => exact.numer().to_string(),
+        Some(exact) => format!(
+            "{{\"numerator\": {}, \"denominator\": {}, \"value\": {}}}",
+            exact.numer(),
+            exact.denom(),
+            json_float(exact.to_f64().unwrap_or(f64::NAN))
+        ),
+        None => match value {
+            StackValue::Float(f) | StackValue::Inexact(f) => json_float(*f),
+            _ => "null".to_string(),
+        },
+    }
+}
+
+pub fn format(calc: &Calculator, value: &StackValue, format: Format) -> String {
+    match format {
+        Format::Plain => stack::format_value(value, false, &calc.modes),
+        Format::Hex => format_hex(calc, value),
+        Format::Json => format_json(value),
+    }
+}
+
+// Prints the top of the stack, or the whole stack from the bottom up.
+pub fn print(calc: &Calculator, format: Format, full_stack: bool) {
+    let depth = calc.stack.depth();
+    let values = if full_stack {
+        calc.stack.get_values(depth).unwrap_or_default()
+    } else {
+        calc.stack.get_values(depth.min(1)).unwrap_or_default()
+    };
+
+    match (format, full_stack) {
+        (Format::Json, true) => {
+            let values: Vec<String> = values
+                .iter()
+                .map(|v| self::format(calc, v, format))
+                .collect();
+            println!("[{}]", values.join(", "));
+        }
+        (Format::Json, false) if values.is_empty() => println!("null"),
+        _ => {
+            for value in &values {
+                println!("{}", self::format(calc, value, format));
+            }
+        }
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// An error as a JSON object with the kind of error, the message as shown in plain format and the
+// details of the error.
+fn format_error_json(error: &CommandError) -> String {
+    let mut fields = vec![
+        ("message", json_string(&error.to_string())),
+        ("command", json_string(&error.command)),
+    ];
+    let kind = match &error.error {
+        Error::StackUnderflow { expected, actual } => {
+            fields.push(("expected", expected.to_string()));
+            fields.push(("actual", actual.to_string()));
+            "stack_underflow"
+        }
+        Error::TypeMismatch { expected, found } => {
+            fields.push(("expected", json_string(error::describe_types(*expected))));
+            fields.push(("found", json_string(found)));
+            "type_mismatch"
+        }
+        Error::Domain(_) => "domain",
+        Error::Overflow(_) => "overflow",
+        Error::Argument(_) => "argument",
+        Error::Failed(_) => "failed",
+        Error::UnknownCommand { suggestions, .. } => {
+            let suggestions: Vec<String> = suggestions.iter().map(|s| json_string(s)).collect();
+            fields.push(("suggestions", format!("[{}]", suggestions.join(", "))));
+            "unknown_command"
+        }
+        Error::InvalidNumber { reason, .. } => {
+            fields.push(("reason", json_string(reason)));
+            "invalid_number"
+        }
+        Error::File { path, reason } => {
+            fields.push(("path", json_string(path)));
+            fields.push(("reason", json_string(reason)));
+            "file"
+        }
+        Error::Script { path, line, error } => {
+            fields.push(("path", json_string(path)));
+            fields.push(("line", line.to_string()));
+            fields.push(("cause", format_error_json(error)));
+            "script"
+        }
+    };
+    fields.insert(0, ("error", json_string(kind)));
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(name, value)| format!("\"{}\": {}", name, value))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+// Prints an error to stderr, after `context` in plain format or as a JSON object.
+pub fn print_error(context: &str, error: &CommandError, format: Format) {
+    match format {
+        Format::Json => eprintln!("{}", format_error_json(error)),
+        _ => eprintln!("{}: {}", context, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_values() {
+        assert_eq!(
+            format_json(&StackValue::Fraction((-3, 4))),
+            "{\"numerator\": -3, \"denominator\": 4, \"value\": -0.75}"
+        );
+        assert_eq!(format_json(&StackValue::Fraction((12, 1))), "12");
+        assert_eq!(
+            format_json(&StackValue::Big(
+                stack::to_big((i128::MAX, 1)) * stack::to_big((4, 1))
+            )),
+            "680564733841876926926749214863536422908"
+        );
+        assert_eq!(format_json(&StackValue::Float(2.5)), "2.5");
+        assert_eq!(format_json(&StackValue::Inexact(1.5e300)), "1.5e300");
+        assert_eq!(format_json(&StackValue::Float(f64::INFINITY)), "null");
+        assert_eq!(format_json(&StackValue::Float(f64::NAN)), "null");
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(
+            json_string("a\"b\\c\n\té"),
+            "\"a\\\"b\\\\c\\u000a\\u0009é\""
+        );
+    }
+
+    #[test]
+    fn test_json_errors() {
+        let error = CommandError {
+            command: "run lib.rpn".to_string(),
+            error: Error::Script {
+                path: "lib.rpn".to_string(),
+                line: 3,
+                error: Box::new(CommandError {
+                    command: "add".to_string(),
+                    error: Error::StackUnderflow {
+                        expected: 2,
+                        actual: 1,
+                    },
+                }),
+            },
+        };
+        assert_eq!(
+            format_error_json(&error),
+            concat!(
+                "{\"error\": \"script\", ",
+                "\"message\": \"lib.rpn:3: add: Not enough values on stack, needs 2 but has 1\", ",
+                "\"command\": \"run lib.rpn\", \"path\": \"lib.rpn\", \"line\": 3, ",
+                "\"cause\": {\"error\": \"stack_underflow\", ",
+                "\"message\": \"add: Not enough values on stack, needs 2 but has 1\", ",
+                "\"command\": \"add\", \"expected\": 2, \"actual\": 1}}"
+            )
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use crate::CmdResult;
+use crate::basic_math;
+use crate::calculator::{Calculator, View};
+use crate::command::{Builtin, Category};
+use crate::error::Error;
+use crate::stack;
+
+// Storage besides the stack: HP-style numbered registers 0 to 99 and named variables. Both live
+// in the same table, a register is just a variable with a numeric name.
+
+const EMPTY: Error = Error::Failed("Register or variable is empty");
+
+fn is_valid_name(name: &str) -> bool {
+    if name.chars().all(|c| c.is_ascii_digit()) {
+        return name.parse::<u8>().is_ok_and(|n| n <= 99);
+    }
+    name.starts_with(|c: char| c.is_alphabetic())
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// Splits "sto rate" into the command and the register or variable name. Register numbers are
+// normalized, so 07 is register 7.
+fn get_name(args: &[&str]) -> Result<String, Error> {
+    match args {
+        [name] if is_valid_name(name) => match name.parse::<u8>() {
+            Ok(number) => Ok(number.to_string()),
+            Err(_) => Ok(name.to_string()),
+        },
+        [_] => Err(Error::Argument(
+            "Registers are 0 to 99, variable names start with a letter",
+        )),
+        [] => Err(Error::Argument("Missing register or variable name")),
+        _ => Err(Error::Argument("Too many arguments")),
+    }
+}
+
+fn store(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    let name = match get_name(args) {
+        Ok(name) => name,
+        Err(e) => return CmdResult::Error(e),
+    };
+    // Like on HP calculators, the value stays on the stack.
+    match calc.stack.get_values(1) {
+        Some(values) => {
+            calc.stack.set_variable(&name, Some(values[0].clone()));
+            CmdResult::Success
+        }
+        None => CmdResult::Error(calc.stack.get_error(1, stack::AcceptedTypes::all())),
+    }
+}
+
+fn recall(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    let name = match get_name(args) {
+        Ok(name) => name,
+        Err(e) => return CmdResult::Error(e),
+    };
+    match calc.stack.get_variable(&name) {
+        Some(value) => {
+            calc.stack.push(value);
+            CmdResult::Success
+        }
+        None => CmdResult::Error(EMPTY),
+    }
+}
+
+// Applies `operation` to the register and the top of the stack, e.g. sto- n stores n - x. The
+// calculation is done on the stack to share the handling of all types and overflow modes.
+fn store_arithmetic(
+    calc: &mut Calculator,
+    args: &[&str],
+    operation: fn(&mut Calculator) -> CmdResult,
+) -> CmdResult {
+    let name = match get_name(args) {
+        Ok(name) => name,
+        Err(e) => return CmdResult::Error(e),
+    };
+    let Some(values) = calc.stack.get_values(1) else {
+        return CmdResult::Error(calc.stack.get_error(1, stack::AcceptedTypes::all()));
+    };
+    let Some(current) = calc.stack.get_variable(&name) else {
+        return CmdResult::Error(EMPTY);
+    };
+
+    // The value stays on the stack, so lastx isn't changed.
+    let last_x = calc.stack.last_x();
+    calc.stack.push(current);
+    calc.stack.push(values[0].clone());
+    let result = match operation(calc) {
+        CmdResult::Success => {
+            let result = calc.stack.get_values(1).unwrap();
+            calc.stack.drop(1);
+            calc.stack.set_variable(&name, Some(result[0].clone()));
+            CmdResult::Success
+        }
+        other => {
+            calc.stack.drop(2);
+            other
+        }
+    };
+    calc.stack.last_x = last_x;
+    result
+}
+
+fn purge(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    let name = match get_name(args) {
+        Ok(name) => name,
+        Err(e) => return CmdResult::Error(e),
+    };
+    if calc.stack.get_variable(&name).is_none() {
+        return CmdResult::Error(EMPTY);
+    }
+    calc.stack.set_variable(&name, None);
+    CmdResult::Success
+}
+
+fn vars(calc: &mut Calculator) -> CmdResult {
+    calc.view = View::Variables;
+    CmdResult::Success
+}
+
+pub fn get_vars(calc: &Calculator) -> Vec<String> {
+    let variables = calc.stack.get_variables();
+    if variables.is_empty() {
+        return vec!["No registers or variables".to_string()];
+    }
+    variables
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{:>8}: {}",
+                name,
+                stack::format_value(value, false, &calc.modes)
+            )
+        })
+        .collect()
+}
+
+const NAME: &[&str] = &["name"];
+
+// 'rate is the same as rcl rate, the quote keeps names apart from commands.
+pub const COMMANDS: Category = Category {
+    name: "Registers and Variables",
+    commands: &[
+        Builtin::new(
+            "sto",
+            "Stores the top value in register 0 to 99 or a named variable, keeping it on the stack.",
+            store,
+        )
+        .with_arguments(NAME)
+        .with_operands(1, stack::AcceptedTypes::all()),
+        Builtin::new(
+            "rcl",
+            "Pushes the value of a register or variable. 'name works as well.",
+            recall,
+        )
+        .with_arguments(NAME),
+        Builtin::new(
+            "sto+",
+            "Adds the top value to a register or variable.",
+            |calc, args| store_arithmetic(calc, args, basic_math::add),
+        )
+        .with_arguments(NAME)
+        .with_operands(1, stack::AcceptedTypes::all()),
+        Builtin::new(
+            "sto-",
+            "Subtracts the top value from a register or variable.",
+            |calc, args| store_arithmetic(calc, args, basic_math::sub),
+        )
+        .with_arguments(NAME)
+        .with_operands(1, stack::AcceptedTypes::all()),
+        Builtin::new(
+            "sto*",
+            "Multiplies a register or variable by the top value.",
+            |calc, args| store_arithmetic(calc, args, basic_math::mul),
+        )
+        .with_arguments(NAME)
+        .with_operands(1, stack::AcceptedTypes::all()),
+        Builtin::new(
+            "sto/",
+            "Divides a register or variable by the top value.",
+            |calc, args| store_arithmetic(calc, args, basic_math::div),
+        )
+        .with_arguments(NAME)
+        .with_operands(1, stack::AcceptedTypes::all()),
+        Builtin::new("purge", "Deletes a register or variable.", purge).with_arguments(NAME),
+        Builtin::new("vars", "Lists all registers and variables.", |calc, _| vars(calc)),
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        assert!(is_valid_name("0"));
+        assert!(is_valid_name("99"));
+        assert!(!is_valid_name("100"));
+        assert!(is_valid_name("rate"));
+        assert!(is_valid_name("r_2"));
+        assert!(!is_valid_name("2r"));
+        assert!(!is_valid_name(""));
+        assert_eq!(get_name(&["007"]), Ok("7".to_string()));
+        assert_eq!(get_name(&["rate"]), Ok("rate".to_string()));
+    }
+
+    #[test]
+    fn test_store_arithmetic() {
+        let mut calc = Calculator::new();
+        assert_eq!(calc.exec("1 2 add 5 sto 0 7 sto+ 0"), Ok(()));
+        assert_eq!(
+            calc.stack.get_variable("0"),
+            Some(stack::StackValue::Fraction((12, 1)))
+        );
+        assert_eq!(
+            calc.stack.last_x(),
+            Some(stack::StackValue::Fraction((2, 1)))
+        );
+    }
+}
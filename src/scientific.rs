@@ -0,0 +1,502 @@
+use crate::CmdResult;
+use crate::basic_math;
+use crate::calculator::Calculator;
+use crate::command::{Builtin, Category};
+use crate::error::Error;
+use crate::modes;
+use crate::stack;
+use crate::stack::FractionType;
+use num_rational::BigRational;
+use num_traits::{Signed, Zero};
+
+// Scientific functions. Most of them only make sense for floats, but the ones that can give an
+// exact answer (e.g. sqrt of a perfect square) keep fractions exact.
+
+const EXACT_TYPES: stack::AcceptedTypes = stack::AcceptedTypes::FRACTIONS
+    .union(stack::AcceptedTypes::BIG)
+    .union(stack::AcceptedTypes::FLOAT);
+
+// Exponents above this are calculated using floats to avoid huge exact results.
+const MAX_EXACT_EXPONENT: i128 = 65536;
+
+fn to_radians(angle: f64, mode: modes::AngleMode) -> f64 {
+    match mode {
+        modes::AngleMode::Deg => angle.to_radians(),
+        modes::AngleMode::Rad => angle,
+        modes::AngleMode::Grad => angle * std::f64::consts::PI / 200.0,
+    }
+}
+
+fn from_radians(angle: f64, mode: modes::AngleMode) -> f64 {
+    match mode {
+        modes::AngleMode::Deg => angle.to_degrees(),
+        modes::AngleMode::Rad => angle,
+        modes::AngleMode::Grad => angle * 200.0 / std::f64::consts::PI,
+    }
+}
+
+fn push_float_result(calc: &mut Calculator, count: usize, result: f64) -> CmdResult {
+    if !result.is_finite() {
+        return CmdResult::Error(Error::Overflow("Result out of range"));
+    }
+    basic_math::push_float(calc, count, result);
+    CmdResult::Success
+}
+
+// Applies a float function to the top of the stack, if the value is within its domain.
+fn float_function(
+    calc: &mut Calculator,
+    function: impl Fn(f64) -> f64,
+    domain: impl Fn(f64) -> bool,
+    domain_error: &'static str,
+) -> CmdResult {
+    if let Some(values) = calc.stack.get(1, stack::AcceptedTypes::FLOAT)
+        && let stack::StackValue::Float(x) = values[0]
+    {
+        if !domain(x) {
+            return CmdResult::Error(Error::Domain(domain_error));
+        }
+        return push_float_result(calc, 1, function(x));
+    }
+    CmdResult::Error(calc.stack.get_error(1, FLOAT))
+}
+
+fn any(_: f64) -> bool {
+    true
+}
+
+// Applies a function that keeps fractions exact, with the float version as fallback.
+fn exact_function(
+    calc: &mut Calculator,
+    fraction: fn(FractionType) -> Option<FractionType>,
+    big: fn(&BigRational) -> BigRational,
+    float: fn(f64) -> f64,
+) -> CmdResult {
+    if let Some(values) = calc.stack.get(1, EXACT_TYPES) {
+        match &values[0] {
+            stack::StackValue::Fraction(a) => {
+                return basic_math::push_fraction_result(calc, 1, fraction(*a), || {
+                    big(&stack::to_big(*a))
+                });
+            }
+            stack::StackValue::Big(a) => {
+                calc.stack.drop(1);
+                calc.stack.push(stack::StackValue::Big(big(a)));
+                return CmdResult::Success;
+            }
+            stack::StackValue::Float(f) => {
+                return push_float_result(calc, 1, float(*f));
+            }
+            _ => {}
+        }
+    }
+    CmdResult::Error(calc.stack.get_error(1, EXACT_TYPES))
+}
+
+fn is_zero(values: &[stack::StackValue]) -> bool {
+    match &values[0] {
+        stack::StackValue::Fraction(a) => a.0 == 0,
+        stack::StackValue::Big(a) => a.is_zero(),
+        stack::StackValue::Float(f) => *f == 0.0,
+        _ => false,
+    }
+}
+
+fn abs(calc: &mut Calculator) -> CmdResult {
+    exact_function(
+        calc,
+        |a| Some((a.0.checked_abs()?, a.1)),
+        |a| a.abs(),
+        f64::abs,
+    )
+}
+
+fn neg(calc: &mut Calculator) -> CmdResult {
+    exact_function(calc, |a| Some((a.0.checked_neg()?, a.1)), |a| -a, |f| -f)
+}
+
+fn inv(calc: &mut Calculator) -> CmdResult {
+    match calc.stack.get(1, EXACT_TYPES) {
+        Some(values) if is_zero(&values) => CmdResult::Error(Error::Domain("Division by zero")),
+        _ => exact_function(
+            calc,
+            |a| basic_math::fraction_div((1, 1), a),
+            |a| a.recip(),
+            f64::recip,
+        ),
+    }
+}
+
+fn sq(calc: &mut Calculator) -> CmdResult {
+    exact_function(
+        calc,
+        |a| basic_math::fraction_mul(a, a),
+        |a| a * a,
+        |f| f * f,
+    )
+}
+
+// Returns the exact square root if both numerator and denominator are perfect squares.
+fn exact_sqrt(value: &BigRational) -> Option<BigRational> {
+    let numer = value.numer().sqrt();
+    let denom = value.denom().sqrt();
+    if &numer * &numer == *value.numer() && &denom * &denom == *value.denom() {
+        Some(BigRational::new(numer, denom))
+    } else {
+        None
+    }
+}
+
+fn sqrt(calc: &mut Calculator) -> CmdResult {
+    if let Some(values) = calc.stack.get(1, EXACT_TYPES) {
+        let exact = match &values[0] {
+            stack::StackValue::Fraction(a) => stack::to_big(*a),
+            stack::StackValue::Big(a) => a.clone(),
+            stack::StackValue::Float(f) if *f < 0.0 => {
+                return CmdResult::Error(Error::Domain("Square root of negative number"));
+            }
+            stack::StackValue::Float(f) => return push_float_result(calc, 1, f.sqrt()),
+            _ => return CmdResult::Error(calc.stack.get_error(1, EXACT_TYPES)),
+        };
+        if exact.is_negative() {
+            return CmdResult::Error(Error::Domain("Square root of negative number"));
+        }
+        if let Some(root) = exact_sqrt(&exact) {
+            calc.stack.drop(1);
+            calc.stack.push(stack::StackValue::Big(root));
+            return CmdResult::Success;
+        }
+        return float_function(
+            calc,
+            f64::sqrt,
+            |x| x >= 0.0,
+            "Square root of negative number",
+        );
+    }
+    CmdResult::Error(calc.stack.get_error(1, EXACT_TYPES))
+}
+
+// Raises a fraction to an integer power. Returns None if the result doesn't fit.
+fn fraction_pow(base: FractionType, exponent: i128) -> Option<FractionType> {
+    let base = if exponent < 0 {
+        basic_math::fraction_div((1, 1), base)?
+    } else {
+        base
+    };
+    let exponent = u32::try_from(exponent.unsigned_abs()).ok()?;
+    Some((base.0.checked_pow(exponent)?, base.1.checked_pow(exponent)?))
+}
+
+fn pow(calc: &mut Calculator) -> CmdResult {
+    if let Some(values) = calc.stack.get(2, EXACT_TYPES) {
+        match (&values[0], &values[1]) {
+            (stack::StackValue::Fraction(y), stack::StackValue::Fraction(x))
+                if x.1 == 1 && x.0.abs() <= MAX_EXACT_EXPONENT =>
+            {
+                if y.0 == 0 && x.0 < 0 {
+                    return CmdResult::Error(Error::Domain("Division by zero"));
+                }
+                let exponent = x.0 as i32;
+                return basic_math::push_fraction_result(calc, 2, fraction_pow(*y, x.0), || {
+                    stack::to_big(*y).pow(exponent)
+                });
+            }
+            (stack::StackValue::Big(y), stack::StackValue::Big(x))
+                if x.is_integer() && x.abs() <= stack::to_big((MAX_EXACT_EXPONENT, 1)) =>
+            {
+                let exponent: i32 = x.to_integer().try_into().unwrap();
+                if y.is_zero() && exponent < 0 {
+                    return CmdResult::Error(Error::Domain("Division by zero"));
+                }
+                calc.stack.drop(2);
+                calc.stack.push(stack::StackValue::Big(y.pow(exponent)));
+                return CmdResult::Success;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(values) = calc.stack.get(2, stack::AcceptedTypes::FLOAT)
+        && let (stack::StackValue::Float(y), stack::StackValue::Float(x)) = (&values[0], &values[1])
+    {
+        if *y < 0.0 && x.fract() != 0.0 {
+            return CmdResult::Error(Error::Domain("Negative base with fractional exponent"));
+        }
+        if *y == 0.0 && *x < 0.0 {
+            return CmdResult::Error(Error::Domain("Division by zero"));
+        }
+        return push_float_result(calc, 2, y.powf(*x));
+    }
+    CmdResult::Error(calc.stack.get_error(2, EXACT_TYPES))
+}
+
+fn positive(x: f64) -> bool {
+    x > 0.0
+}
+
+fn unit_range(x: f64) -> bool {
+    (-1.0..=1.0).contains(&x)
+}
+
+// Trigonometric functions take angles in the current angle mode.
+fn trig(calc: &mut Calculator, function: fn(f64) -> f64) -> CmdResult {
+    let angle = calc.modes.get_angle_mode();
+    float_function(
+        calc,
+        |x| function(to_radians(x, angle)),
+        any,
+        "Value out of range",
+    )
+}
+
+// The tangent is undefined at odd multiples of a right angle. They are checked in the angle mode,
+// the cosine of the converted angle is never exactly zero.
+fn tan(calc: &mut Calculator) -> CmdResult {
+    let angle = calc.modes.get_angle_mode();
+    let right_angle = match angle {
+        modes::AngleMode::Deg => 90.0,
+        modes::AngleMode::Rad => std::f64::consts::FRAC_PI_2,
+        modes::AngleMode::Grad => 100.0,
+    };
+    float_function(
+        calc,
+        |x| to_radians(x, angle).tan(),
+        |x| {
+            let quarters = x / right_angle;
+            quarters.fract() != 0.0 || quarters % 2.0 == 0.0
+        },
+        "Tangent is undefined",
+    )
+}
+
+// Inverse trigonometric functions return angles in the current angle mode.
+fn inverse_trig(calc: &mut Calculator, function: fn(f64) -> f64) -> CmdResult {
+    let angle = calc.modes.get_angle_mode();
+    float_function(
+        calc,
+        |x| from_radians(function(x), angle),
+        unit_range,
+        "Value out of range",
+    )
+}
+
+const FLOAT: stack::AcceptedTypes = stack::AcceptedTypes::FLOAT;
+
+pub const COMMANDS: Category = Category {
+    name: "Scientific",
+    commands: &[
+        Builtin::new(
+            "sqrt",
+            "Square root of the top number on the stack.",
+            |calc, _| sqrt(calc),
+        )
+        .with_operands(1, EXACT_TYPES),
+        Builtin::new(
+            "pow",
+            "Raises the second number on the stack to the power of the top number.",
+            |calc, _| pow(calc),
+        )
+        .with_operands(2, EXACT_TYPES),
+        Builtin::new(
+            "exp",
+            "e raised to the power of the top number on the stack.",
+            |calc, _| float_function(calc, f64::exp, any, "Value out of range"),
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "ln",
+            "Natural logarithm of the top number on the stack.",
+            |calc, _| float_function(calc, f64::ln, positive, "Logarithm of non-positive number"),
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "log10",
+            "Base 10 logarithm of the top number on the stack.",
+            |calc, _| {
+                float_function(
+                    calc,
+                    f64::log10,
+                    positive,
+                    "Logarithm of non-positive number",
+                )
+            },
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "log2",
+            "Base 2 logarithm of the top number on the stack.",
+            |calc, _| {
+                float_function(
+                    calc,
+                    f64::log2,
+                    positive,
+                    "Logarithm of non-positive number",
+                )
+            },
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "sin",
+            "Sine of the top number on the stack, using the current angle mode.",
+            |calc, _| trig(calc, f64::sin),
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "cos",
+            "Cosine of the top number on the stack, using the current angle mode.",
+            |calc, _| trig(calc, f64::cos),
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "tan",
+            "Tangent of the top number on the stack, using the current angle mode.",
+            |calc, _| tan(calc),
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "asin",
+            "Arcsine of the top number on the stack, in the current angle mode.",
+            |calc, _| inverse_trig(calc, f64::asin),
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "acos",
+            "Arccosine of the top number on the stack, in the current angle mode.",
+            |calc, _| inverse_trig(calc, f64::acos),
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "atan",
+            "Arctangent of the top number on the stack, in the current angle mode.",
+            |calc, _| {
+                let angle = calc.modes.get_angle_mode();
+                float_function(
+                    calc,
+                    |x| from_radians(x.atan(), angle),
+                    any,
+                    "Value out of range",
+                )
+            },
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "sinh",
+            "Hyperbolic sine of the top number on the stack.",
+            |calc, _| float_function(calc, f64::sinh, any, "Value out of range"),
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "cosh",
+            "Hyperbolic cosine of the top number on the stack.",
+            |calc, _| float_function(calc, f64::cosh, any, "Value out of range"),
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "tanh",
+            "Hyperbolic tangent of the top number on the stack.",
+            |calc, _| float_function(calc, f64::tanh, any, "Value out of range"),
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "asinh",
+            "Inverse hyperbolic sine of the top number on the stack.",
+            |calc, _| float_function(calc, f64::asinh, any, "Value out of range"),
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "acosh",
+            "Inverse hyperbolic cosine of the top number on the stack.",
+            |calc, _| float_function(calc, f64::acosh, |x| x >= 1.0, "Value out of range"),
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "atanh",
+            "Inverse hyperbolic tangent of the top number on the stack.",
+            |calc, _| {
+                float_function(
+                    calc,
+                    f64::atanh,
+                    |x| x > -1.0 && x < 1.0,
+                    "Value out of range",
+                )
+            },
+        )
+        .with_operands(1, FLOAT),
+        Builtin::new(
+            "abs",
+            "Absolute value of the top number on the stack.",
+            |calc, _| abs(calc),
+        )
+        .with_operands(1, EXACT_TYPES),
+        Builtin::new("neg", "Negates the top number on the stack.", |calc, _| {
+            neg(calc)
+        })
+        .with_operands(1, EXACT_TYPES),
+        Builtin::new(
+            "inv",
+            "Reciprocal (1/x) of the top number on the stack.",
+            |calc, _| inv(calc),
+        )
+        .with_operands(1, EXACT_TYPES),
+        Builtin::new("sq", "Squares the top number on the stack.", |calc, _| {
+            sq(calc)
+        })
+        .with_aliases(&["x^2"])
+        .with_operands(1, EXACT_TYPES),
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exact_sqrt() {
+        assert_eq!(
+            exact_sqrt(&stack::to_big((9, 16))),
+            Some(stack::to_big((3, 4)))
+        );
+        assert_eq!(exact_sqrt(&stack::to_big((2, 1))), None);
+    }
+
+    #[test]
+    fn test_sq() {
+        let mut calc = Calculator::new();
+        assert_eq!(calc.exec("2/3 sq -3 x^2"), Ok(()));
+        assert_eq!(
+            calc.stack.get_values(2),
+            Some(vec![
+                stack::StackValue::Fraction((4, 9)),
+                stack::StackValue::Fraction((9, 1))
+            ])
+        );
+    }
+
+    #[test]
+    fn test_fraction_pow() {
+        assert_eq!(fraction_pow((2, 3), 3), Some((8, 27)));
+        assert_eq!(fraction_pow((-2, 3), -3), Some((-27, 8)));
+        assert_eq!(fraction_pow((2, 1), 127), None);
+    }
+
+    #[test]
+    fn test_tan() {
+        let mut calc = Calculator::new();
+        for input in [
+            "90 tan",
+            "-270 tan",
+            "grad 100 tan",
+            "rad 1 asin tan",
+            "1 asin 3 mul tan",
+        ] {
+            assert_eq!(
+                calc.exec(input).unwrap_err().error,
+                Error::Domain("Tangent is undefined"),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(calc.exec("deg 180 tan 45 tan"), Ok(()));
+        assert_eq!(calc.stack.depth(), 2);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::CmdResult;
+use crate::calculator::Calculator;
+use crate::command::{Builtin, Category};
+use crate::error::{CommandError, Error};
+use std::fs;
+use std::path::Path;
+
+// Script files contain the same input as typed interactively, one or more tokens per line.
+//   # starts a comment that runs to the end of the line
+//   a line ending in \ continues on the next line
+//   include other.rpn runs another script, relative to the including file
+// Each line is executed like an input line, so a failing line is rolled back. Errors are reported
+// with the file name and line number.
+
+// Deep enough for any sensible use, shallow enough to stop scripts including or running
+// themselves.
+const MAX_DEPTH: usize = 16;
+
+// Removes a comment, which starts with a # at the beginning of a word so '#' still works.
+fn strip_comment(line: &str) -> &str {
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        if c == '#' && previous.is_whitespace() {
+            return &line[..i];
+        }
+        previous = c;
+    }
+    line
+}
+
+// Joins continued lines, returning each logical line with the number of its first line.
+fn logical_lines(contents: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    for (number, line) in contents.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        let (line, continued) = match line.strip_suffix('\\') {
+            Some(line) => (line.trim_end(), true),
+            None => (line, false),
+        };
+        let (start, mut text) = current.take().unwrap_or((number + 1, String::new()));
+        if !text.is_empty() && !line.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(line);
+        if continued {
+            current = Some((start, text));
+        } else if !text.trim().is_empty() {
+            lines.push((start, text));
+        }
+    }
+    if let Some(line) = current
+        && !line.1.trim().is_empty()
+    {
+        lines.push(line);
+    }
+    lines
+}
+
+fn run_file(calc: &mut Calculator, path: &Path) -> Result<(), Error> {
+    let file_error = |reason: String| Error::File {
+        path: path.display().to_string(),
+        reason,
+    };
+    let contents = fs::read_to_string(path).map_err(|e| file_error(e.to_string()))?;
+    let line_error = |line, error| Error::Script {
+        path: path.display().to_string(),
+        line,
+        error: Box::new(error),
+    };
+
+    for (number, line) in logical_lines(&contents) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.first() == Some(&"include") {
+            let file = match words[1..] {
+                [file] => file,
+                _ => {
+                    return Err(line_error(
+                        number,
+                        CommandError {
+                            command: line.clone(),
+                            error: Error::Argument("include needs exactly one file"),
+                        },
+                    ));
+                }
+            };
+            run(calc, Path::new(file))?;
+            continue;
+        }
+
+        calc.exec(&line).map_err(|e| line_error(number, e))?;
+    }
+    Ok(())
+}
+
+// Runs a script. Scripts running or including other scripts share one nesting limit, and their
+// relative paths are relative to the calling script.
+pub fn run(calc: &mut Calculator, path: &Path) -> Result<(), Error> {
+    let path = match calc.scripts.last().and_then(|script| script.parent()) {
+        Some(directory) => directory.join(path),
+        None => path.to_path_buf(),
+    };
+    if calc.scripts.len() >= MAX_DEPTH {
+        return Err(Error::File {
+            path: path.display().to_string(),
+            reason: "scripts nested too deeply".to_string(),
+        });
+    }
+    calc.scripts.push(path.clone());
+    let result = run_file(calc, &path);
+    calc.scripts.pop();
+    result
+}
+
+fn run_command(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    match run(calc, Path::new(args[0])) {
+        Ok(()) => CmdResult::Success,
+        Err(e) => CmdResult::Error(e),
+    }
+}
+
+pub const COMMANDS: Category = Category {
+    name: "Scripts",
+    commands: &[Builtin::new(
+        "run",
+        "Runs a script file. Scripts can use # comments, \\ line continuation and include other files.",
+        run_command,
+    )
+    .with_arguments(&["file"])],
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_logical_lines() {
+        let script = "# constants\n1 2 \\\n  add # sum\n\n'#' 3\n";
+        assert_eq!(
+            logical_lines(script),
+            vec![(2, "1 2 add".to_string()), (5, "'#' 3".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_undo_in_script() {
+        let path = std::env::temp_dir().join(format!("rpn-undo-{}.rpn", std::process::id()));
+        fs::write(&path, "1\nundo\n7\n").unwrap();
+        let mut calc = Calculator::new();
+        assert_eq!(calc.exec("5"), Ok(()));
+
+        // The script is a single step, it can't undo what came before it.
+        let error = calc.exec(&format!("run {}", path.display())).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(error.error, Error::Script { line: 2, .. }));
+        assert_eq!(calc.stack.depth(), 1);
+        assert_eq!(calc.history.get_commands().0, vec!["5"]);
+        assert_eq!(calc.exec("undo"), Ok(()));
+        assert_eq!(calc.stack.depth(), 0);
+    }
+
+    #[test]
+    fn test_nesting() {
+        let directory = std::env::temp_dir().join(format!("rpn-nesting-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        // Relative to the running script, not to the working directory.
+        fs::write(directory.join("loop.rpn"), "1\nrun loop.rpn\n").unwrap();
+        let mut calc = Calculator::new();
+        let error = calc
+            .exec(&format!("run {}", directory.join("loop.rpn").display()))
+            .unwrap_err();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(error.to_string().ends_with("scripts nested too deeply"));
+        assert_eq!(calc.stack.depth(), 0);
+        assert!(calc.scripts.is_empty());
+    }
+}
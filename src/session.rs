@@ -0,0 +1,358 @@
+use crate::CmdResult;
+use crate::calculator::Calculator;
+use crate::command::{Builtin, Category};
+use crate::error::Error;
+use crate::modes;
+use crate::stack;
+use crate::stack::StackValue;
+use num_rational::BigRational;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Sessions keep the stack, variables, modes and undo history between runs. They're saved as TOML
+// in the XDG state directory: the last session in rpn/session.toml and named workspaces in
+// rpn/workspaces/<name>.toml. Values are written like number literals and modes as the commands
+// setting them, so the files can be read and edited by hand.
+
+// Increased whenever the format changes in a way older versions can't read. Fields that are
+// added later get defaults, so older files can always be loaded.
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct State {
+    // Bottom first.
+    stack: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    variables: BTreeMap<String, String>,
+    last_x: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HistoryEntry {
+    command: String,
+    #[serde(default)]
+    state: State,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct SessionFile {
+    version: u32,
+    modes: Vec<String>,
+    state: State,
+    undo: Vec<HistoryEntry>,
+    redo: Vec<HistoryEntry>,
+}
+
+fn encode(value: &StackValue) -> String {
+    match value {
+        StackValue::Integer(i) => i.to_string(),
+        StackValue::Fraction((num, 1)) => num.to_string(),
+        StackValue::Fraction((num, denom)) => format!("{}/{}", num, denom),
+        StackValue::Big(big) => big.to_string(),
+        // Debug keeps the full precision and always shows a decimal point or exponent.
+        StackValue::Float(f) => format!("{:?}", f),
+        StackValue::Inexact(f) => format!("~{:?}", f),
+    }
+}
+
+fn decode(text: &str) -> Result<StackValue, String> {
+    if let Some(float) = text.strip_prefix('~') {
+        return float
+            .parse()
+            .map(StackValue::Inexact)
+            .map_err(|_| format!("invalid value '{}'", text));
+    }
+    if let Ok(exact) = text.parse::<BigRational>() {
+        return Ok(match stack::from_big(&exact) {
+            Some(frac) => StackValue::Fraction(frac),
+            None => StackValue::Big(exact),
+        });
+    }
+    text.parse()
+        .map(StackValue::Float)
+        .map_err(|_| format!("invalid value '{}'", text))
+}
+
+fn encode_state(stack: &stack::Stack) -> State {
+    State {
+        stack: stack.values.iter().map(encode).collect(),
+        variables: stack
+            .variables
+            .iter()
+            .map(|(name, value)| (name.clone(), encode(value)))
+            .collect(),
+        last_x: stack.last_x.as_ref().map(encode),
+    }
+}
+
+fn decode_state(state: &State) -> Result<stack::Stack, String> {
+    let mut variables = BTreeMap::new();
+    for (name, value) in &state.variables {
+        variables.insert(name.clone(), decode(value)?);
+    }
+    Ok(stack::Stack {
+        values: state
+            .stack
+            .iter()
+            .map(|v| decode(v))
+            .collect::<Result<_, _>>()?,
+        variables,
+        last_x: state.last_x.as_deref().map(decode).transpose()?,
+    })
+}
+
+fn encode_history(history: &[stack::HistoryEntry]) -> Vec<HistoryEntry> {
+    history
+        .iter()
+        .map(|entry| HistoryEntry {
+            command: entry.command.clone(),
+            state: encode_state(&entry.stack),
+        })
+        .collect()
+}
+
+fn decode_history(history: &[HistoryEntry]) -> Result<Vec<stack::HistoryEntry>, String> {
+    history
+        .iter()
+        .map(|entry| {
+            Ok(stack::HistoryEntry {
+                command: entry.command.clone(),
+                stack: decode_state(&entry.state)?,
+            })
+        })
+        .collect()
+}
+
+// The session as the text of a session file.
+pub fn to_text(calc: &Calculator) -> String {
+    let (undo, redo) = calc.history.get_entries();
+    let file = SessionFile {
+        version: VERSION,
+        modes: calc.modes.get_mode_commands(),
+        state: encode_state(&calc.stack),
+        undo: encode_history(undo),
+        redo: encode_history(redo),
+    };
+    toml::to_string(&file).expect("Session can't be serialized")
+}
+
+// Restores a session from the text of a session file, replacing the modes set before. Everything
+// is checked before anything is changed, so a broken file leaves the session as is.
+pub fn from_text(calc: &mut Calculator, text: &str) -> Result<(), String> {
+    let file: SessionFile = toml::from_str(text).map_err(|e| e.message().to_string())?;
+    if file.version > VERSION {
+        return Err(format!(
+            "saved by a newer version (format {}, this version reads up to {})",
+            file.version, VERSION
+        ));
+    }
+    let stack = decode_state(&file.state)?;
+    let undo = decode_history(&file.undo)?;
+    let redo = decode_history(&file.redo)?;
+
+    // Modes this version doesn't know are skipped.
+    calc.modes.reset();
+    for command in &file.modes {
+        let _ = modes::set_mode(calc, command);
+    }
+    calc.stack = stack;
+    calc.history.set_entries(undo, redo);
+    Ok(())
+}
+
+// The XDG state directory of rpn, also used for the input history of the terminal frontend.
+pub fn get_directory() -> Option<PathBuf> {
+    Some(dirs::state_dir().or_else(dirs::data_local_dir)?.join("rpn"))
+}
+
+fn file_error(path: &Path, reason: impl ToString) -> Error {
+    Error::File {
+        path: path.display().to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+fn save_file(calc: &Calculator, path: &Path) -> Result<(), Error> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory).map_err(|e| file_error(directory, e))?;
+    }
+    fs::write(path, to_text(calc)).map_err(|e| file_error(path, e))
+}
+
+fn load_file(calc: &mut Calculator, path: &Path) -> Result<(), Error> {
+    let text = fs::read_to_string(path).map_err(|e| file_error(path, e))?;
+    from_text(calc, &text).map_err(|e| file_error(path, e))
+}
+
+fn get_session_path() -> Result<PathBuf, Error> {
+    get_directory()
+        .map(|directory| directory.join("session.toml"))
+        .ok_or(Error::Failed("No state directory"))
+}
+
+// Saves the session when quitting.
+pub fn save_session(calc: &Calculator) -> Result<(), Error> {
+    save_file(calc, &get_session_path()?)
+}
+
+// Restores the session saved by the last run, if there is one.
+pub fn load_session(calc: &mut Calculator) -> Result<(), Error> {
+    let path = get_session_path()?;
+    if !path.exists() {
+        return Ok(());
+    }
+    load_file(calc, &path)
+}
+
+fn get_workspace_path(args: &[&str]) -> Result<PathBuf, Error> {
+    let name = match args {
+        [name] => name,
+        [] => return Err(Error::Argument("Missing workspace name")),
+        _ => return Err(Error::Argument("Too many arguments")),
+    };
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(Error::Argument(
+            "Workspace names can only contain letters, digits, _ and -",
+        ));
+    }
+    match get_directory() {
+        Some(directory) => Ok(directory.join("workspaces").join(format!("{}.toml", name))),
+        None => Err(Error::Failed("No state directory")),
+    }
+}
+
+fn save(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    match get_workspace_path(args) {
+        Ok(path) => match save_file(calc, &path) {
+            Ok(()) => CmdResult::Success,
+            Err(e) => CmdResult::Error(e),
+        },
+        Err(e) => CmdResult::Error(e),
+    }
+}
+
+fn load(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    let path = match get_workspace_path(args) {
+        Ok(path) => path,
+        Err(e) => return CmdResult::Error(e),
+    };
+    if !path.exists() {
+        return CmdResult::Error(Error::Failed("No such workspace"));
+    }
+    match load_file(calc, &path) {
+        Ok(()) => CmdResult::Success,
+        Err(e) => CmdResult::Error(e),
+    }
+}
+
+pub const COMMANDS: Category = Category {
+    name: "Sessions",
+    commands: &[
+        Builtin::new(
+            "save",
+            "Saves the stack, variables, modes and undo history as a named workspace.",
+            save,
+        )
+        .with_arguments(&["name"]),
+        Builtin::new(
+            "load",
+            "Replaces the current session with a saved workspace.",
+            load,
+        )
+        .with_arguments(&["name"]),
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values() {
+        for value in [
+            StackValue::Fraction((-3, 4)),
+            StackValue::Fraction((12, 1)),
+            StackValue::Float(2.5),
+            StackValue::Float(1e300),
+            StackValue::Inexact(-1.25e-7),
+            StackValue::Big(stack::to_big((i128::MAX, 1)) * stack::to_big((4, 1))),
+        ] {
+            assert_eq!(decode(&encode(&value)), Ok(value));
+        }
+        assert_eq!(decode("6/8"), Ok(StackValue::Fraction((3, 4))));
+        assert!(decode("x").is_err());
+    }
+
+    #[test]
+    fn test_session() {
+        let mut calc = Calculator::new();
+        assert_eq!(calc.exec("1/3 sto rate hex"), Ok(()));
+
+        let text = to_text(&calc);
+        assert!(text.starts_with("version = 1\n"));
+        assert!(text.contains("stack = [\"1/3\"]"));
+
+        let mut restored = Calculator::new();
+        assert_eq!(from_text(&mut restored, &text), Ok(()));
+        assert_eq!(
+            restored.stack.get_values(1),
+            Some(vec![StackValue::Fraction((1, 3))])
+        );
+        assert_eq!(
+            restored.stack.get_variable("rate"),
+            Some(StackValue::Fraction((1, 3)))
+        );
+        assert!(matches!(restored.modes.get_radix(), modes::Radix::Hex));
+        assert_eq!(restored.history.get_commands().0, vec!["1/3", "sto rate"]);
+
+        assert!(from_text(&mut restored, "version = 2").is_err());
+        assert!(from_text(&mut restored, "[state]\nstack = [\"1/0\"]").is_err());
+        assert_eq!(restored.stack.depth(), 1);
+    }
+
+    #[test]
+    fn test_load_keeps_redo() {
+        let mut calc = Calculator::new();
+        assert_eq!(calc.exec("1 2 undo"), Ok(()));
+        let text = to_text(&calc);
+
+        // Loading a workspace runs as a command, which must not clear the restored redo steps.
+        let mut restored = Calculator::new();
+        assert_eq!(restored.exec("5"), Ok(()));
+        restored.history.begin_command(&restored.stack);
+        assert_eq!(from_text(&mut restored, &text), Ok(()));
+        restored
+            .history
+            .end_command(&restored.stack, "load ws", true);
+        assert_eq!(
+            restored.history.get_commands(),
+            (vec!["1".to_string()], vec!["2".to_string()])
+        );
+        assert_eq!(restored.exec("redo"), Ok(()));
+        assert_eq!(restored.stack.depth(), 2);
+    }
+
+    #[test]
+    fn test_file_errors() {
+        let mut calc = Calculator::new();
+        let path = std::env::temp_dir().join(format!("rpn-session-{}.toml", std::process::id()));
+        fs::write(&path, "version = 99").unwrap();
+        let error = load_file(&mut calc, &path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            &error,
+            Error::File { reason, .. } if reason.starts_with("saved by a newer version")
+        ));
+        assert!(matches!(
+            load_file(&mut calc, &path),
+            Err(Error::File { .. })
+        ));
+    }
+}
@@ -1,269 +1,641 @@
 use bitflags::bitflags;
-use std::sync::Mutex;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, ToPrimitive};
+use std::collections::BTreeMap;
 
+use crate::error::Error;
 use crate::modes;
 
-type FractionType = (i128, i128);
+pub type FractionType = (i128, i128);
 
 bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq)]
     pub struct AcceptedTypes: usize {
         const INTEGERS = 0b00000001;
         const FRACTIONS = 0b00000010;
         const FLOAT = 0b00000100;
+        const BIG = 0b00001000;
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+impl AcceptedTypes {
+    // Whether get() can return the value as one of these types.
+    pub fn accepts(self, value: &StackValue) -> bool {
+        match value {
+            StackValue::Integer(_) | StackValue::Fraction((_, 1)) => !self.is_empty(),
+            StackValue::Fraction(_) => self.intersects(Self::FRACTIONS | Self::BIG | Self::FLOAT),
+            StackValue::Big(_) => self.intersects(Self::BIG | Self::FLOAT),
+            StackValue::Float(_) | StackValue::Inexact(_) => self.contains(Self::FLOAT),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum StackValue {
     Integer(i128),
     Float(f64),
     Fraction(FractionType),
+    // A float produced by an exact operation that overflowed, shown with a marker.
+    Inexact(f64),
+    // Exact values that don't fit in a FractionType. Only used when needed, see push().
+    Big(BigRational),
+}
+
+impl StackValue {
+    // The type as named in error messages.
+    pub fn get_type_name(&self) -> &'static str {
+        match self {
+            StackValue::Integer(_) | StackValue::Fraction((_, 1)) => "integer",
+            StackValue::Fraction(_) => "fraction",
+            StackValue::Big(big) if big.is_integer() => "integer",
+            StackValue::Big(_) => "fraction",
+            StackValue::Float(_) | StackValue::Inexact(_) => "float",
+        }
+    }
+}
+
+// Registers and variables are kept with the stack so undo restores them as well.
+#[derive(Clone, Default, PartialEq)]
+pub struct Stack {
+    pub values: Vec<StackValue>,
+    pub variables: BTreeMap<String, StackValue>,
+    // The top value consumed by the last operation, for lastx.
+    pub last_x: Option<StackValue>,
 }
 
+// A state of the stack together with the command that changed it.
 #[derive(Clone)]
-struct Stack {
-    values: Vec<StackValue>,
+pub struct HistoryEntry {
+    pub stack: Stack,
+    pub command: String,
 }
 
-static STACK: Mutex<Stack> = Mutex::new(Stack { values: Vec::new() });
-static STACKBACKUP: Mutex<Vec<Stack>> = Mutex::new(Vec::new());
+// Undo works on whole commands. Each command runs as a transaction, see begin_command(), and
+// the state before it is recorded when it's done. Commands running other commands, like scripts,
+// are a single step.
+#[derive(Clone)]
+pub struct History {
+    // The states before each command, oldest first.
+    undo: Vec<HistoryEntry>,
+    // The states after each undone command, most recently undone last.
+    redo: Vec<HistoryEntry>,
+    // Maximum number of undo steps, 0 for no limit.
+    depth: usize,
+    // The state before the outermost running command, and how deeply commands are nested.
+    pending: Option<Stack>,
+    nesting: usize,
+}
 
-fn backup_stack(stack: &Stack) {
-    let mut backup = STACKBACKUP.lock().unwrap();
-    backup.push(stack.clone());
+pub const DEFAULT_HISTORY_DEPTH: usize = 1000;
 
-    if backup.len() > 10 {
-        backup.remove(0);
+impl Default for History {
+    fn default() -> History {
+        History {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            depth: DEFAULT_HISTORY_DEPTH,
+            pending: None,
+            nesting: 0,
+        }
     }
 }
 
-pub fn drop(count: usize) {
-    let mut stack = STACK.lock().unwrap();
+impl History {
+    // Forgets the oldest undo steps beyond the maximum depth.
+    fn trim(&mut self) {
+        if self.depth > 0 && self.undo.len() > self.depth {
+            let excess = self.undo.len() - self.depth;
+            self.undo.drain(..excess);
+        }
+    }
 
-    if count > stack.values.len() {
-        return;
+    pub fn begin_command(&mut self, stack: &Stack) {
+        if self.nesting == 0 {
+            self.pending = Some(stack.clone());
+        }
+        self.nesting += 1;
     }
 
-    let start = stack.values.len() - count;
-    backup_stack(&stack);
-    stack.values.drain(start..);
-}
+    // Finishes a command, recording it as an undo step if it changed anything. Commands that
+    // failed aren't recorded, the caller rolls them back.
+    pub fn end_command(&mut self, stack: &Stack, command: &str, success: bool) {
+        self.nesting = self.nesting.saturating_sub(1);
+        if self.nesting > 0 {
+            return;
+        }
+        let Some(before) = self.pending.take() else {
+            return;
+        };
+        if !success || before == *stack {
+            return;
+        }
 
-pub fn undo() {
-    let mut stack = STACK.lock().unwrap();
-    let mut backup = STACKBACKUP.lock().unwrap();
+        self.undo.push(HistoryEntry {
+            stack: before,
+            command: command.to_string(),
+        });
+        self.redo.clear();
+        self.trim();
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        self.trim();
+    }
+
+    // Whether the running command was started by another one, e.g. by a script.
+    pub fn is_nested(&self) -> bool {
+        self.nesting > 1
+    }
 
-    if let Some(previous) = backup.pop() {
-        *stack = previous;
+    // Undoes up to `steps` commands, returning how many were undone. The running command, i.e.
+    // undo itself, isn't recorded. Nested commands can't undo, see is_nested().
+    pub fn undo(&mut self, stack: &mut Stack, steps: usize) -> usize {
+        if self.is_nested() {
+            return 0;
+        }
+        self.pending = None;
+        let mut done = 0;
+        while done < steps {
+            let Some(entry) = self.undo.pop() else {
+                break;
+            };
+            let after = std::mem::replace(stack, entry.stack);
+            self.redo.push(HistoryEntry {
+                stack: after,
+                command: entry.command,
+            });
+            done += 1;
+        }
+        done
+    }
+
+    pub fn redo(&mut self, stack: &mut Stack, steps: usize) -> usize {
+        if self.is_nested() {
+            return 0;
+        }
+        self.pending = None;
+        let mut done = 0;
+        while done < steps {
+            let Some(entry) = self.redo.pop() else {
+                break;
+            };
+            let before = std::mem::replace(stack, entry.stack);
+            self.undo.push(HistoryEntry {
+                stack: before,
+                command: entry.command,
+            });
+            done += 1;
+        }
+        done
+    }
+
+    // The commands that can be undone, oldest first, and the ones that can be redone, next
+    // first.
+    pub fn get_commands(&self) -> (Vec<String>, Vec<String>) {
+        (
+            self.undo.iter().map(|e| e.command.clone()).collect(),
+            self.redo.iter().rev().map(|e| e.command.clone()).collect(),
+        )
+    }
+
+    // The undo and redo entries, for saving sessions.
+    pub fn get_entries(&self) -> (&[HistoryEntry], &[HistoryEntry]) {
+        (&self.undo, &self.redo)
+    }
+
+    // Replaces the history, e.g. when loading a session. The running command isn't recorded, it
+    // would clear the restored redo steps.
+    pub fn set_entries(&mut self, undo: Vec<HistoryEntry>, redo: Vec<HistoryEntry>) {
+        self.pending = None;
+        self.undo = undo;
+        self.redo = redo;
+        self.trim();
     }
 }
 
-pub fn push(value: StackValue) {
-    let mut stack = STACK.lock().unwrap();
+pub fn gcd(a: i128, b: i128) -> i128 {
+    let mut a = a.unsigned_abs();
+    let mut b = b.unsigned_abs();
+    while b != 0 {
+        let temp = b;
+        b = a % b;
+        a = temp;
+    }
+    a as i128
+}
 
-    let mut value = value;
+pub fn to_big(frac: FractionType) -> BigRational {
+    BigRational::new_raw(BigInt::from(frac.0), BigInt::from(frac.1))
+}
+
+pub fn from_big(big: &BigRational) -> Option<FractionType> {
+    Some((big.numer().to_i128()?, big.denom().to_i128()?))
+}
+
+impl Stack {
+    pub fn drop(&mut self, count: usize) {
+        if count > self.values.len() {
+            return;
+        }
+
+        let start = self.values.len() - count;
+        if count > 0 {
+            self.last_x = self.values.last().cloned();
+        }
+        self.values.drain(start..);
+    }
+
+    // Replaces the top `count` values, given bottom first. Unlike drop() this
+    // doesn't change lastx, it's meant for rearranging the stack. Returns false if the stack holds
+    // fewer than `count` values.
+    pub fn replace(&mut self, count: usize, values: Vec<StackValue>) -> bool {
+        if count > self.values.len() {
+            return false;
+        }
 
-    if let StackValue::Float(f) = value {
-        if (f * 1000.0).fract() == 0.0
+        let start = self.values.len() - count;
+        self.values.splice(start.., values);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    pub fn last_x(&self) -> Option<StackValue> {
+        self.last_x.clone()
+    }
+
+    pub fn push(&mut self, value: StackValue) {
+        let mut value = value;
+
+        if let StackValue::Float(f) = value
+            && (f * 1000.0).fract() == 0.0
             && f * 1000.0 >= (i128::MIN as f64)
             && f * 1000.0 <= (i128::MAX as f64)
         {
-            // If float is an integer within i128 range, convert to fraction
+            // If float is an integer within i128 range, convert to fraction. Inexact values stay
+            // floats so they are still shown as inexact.
             value = StackValue::Fraction(((f * 1000.0) as i128, 1000));
         }
-    }
 
-    if let StackValue::Fraction((num, denom)) = value {
-        let gcd = |a: i128, b: i128| {
-            let mut a = a.abs();
-            let mut b = b.abs();
-            while b != 0 {
-                let temp = b;
-                b = a % b;
-                a = temp;
+        if let StackValue::Big(big) = &value {
+            // Switch back to the cheaper representation whenever the value fits.
+            if let Some(frac) = from_big(big) {
+                value = StackValue::Fraction(frac);
             }
-            a
-        };
+        }
 
-        let divisor = gcd(num, denom);
-        let simplified = (num / divisor, denom / divisor);
-        if simplified.1 < 0 {
-            // Ensure denominator is positive
-            value = StackValue::Fraction((-simplified.0, -simplified.1));
-        } else {
-            value = StackValue::Fraction(simplified);
+        if let StackValue::Fraction((num, denom)) = value {
+            let divisor = gcd(num, denom);
+            let simplified = (num / divisor, denom / divisor);
+            if simplified.1 < 0 {
+                // Ensure denominator is positive
+                value = StackValue::Fraction((-simplified.0, -simplified.1));
+            } else {
+                value = StackValue::Fraction(simplified);
+            }
         }
-    }
 
-    // Handle ints as fractions to avoid unnecessary complexity in all operations
-    if let StackValue::Integer(i) = value {
-        if i >= i128::MIN && i <= i128::MAX {
+        // Handle ints as fractions to avoid unnecessary complexity in all operations
+        if let StackValue::Integer(i) = value {
             // Convert integer to fraction
             value = StackValue::Fraction((i, 1));
         }
+
+        self.values.push(value);
     }
 
-    backup_stack(&stack);
-    stack.values.push(value);
-}
+    pub fn get_variable(&self, name: &str) -> Option<StackValue> {
+        self.variables.get(name).cloned()
+    }
 
-pub fn get(count: usize, types: AcceptedTypes) -> Option<Vec<StackValue>> {
-    let stack = STACK.lock().unwrap();
+    pub fn get_variables(&self) -> Vec<(String, StackValue)> {
+        self.variables
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
 
-    if count > stack.values.len() {
-        return None;
+    // Stores a value, or removes the variable if `value` is None.
+    pub fn set_variable(&mut self, name: &str, value: Option<StackValue>) {
+        match value {
+            Some(value) => self.variables.insert(name.to_string(), value),
+            None => self.variables.remove(name),
+        };
     }
 
-    let start = stack.values.len() - count;
+    pub fn get(&self, count: usize, types: AcceptedTypes) -> Option<Vec<StackValue>> {
+        if count > self.values.len() {
+            return None;
+        }
 
-    if types.contains(AcceptedTypes::INTEGERS) {
-        let mut result = Vec::with_capacity(count);
-        for v in &stack.values[start..] {
-            match v {
-                StackValue::Fraction(frac) => {
-                    if frac.1 != 1 {
+        let start = self.values.len() - count;
+
+        if types.contains(AcceptedTypes::INTEGERS) {
+            let mut result = Vec::with_capacity(count);
+            for v in &self.values[start..] {
+                match v {
+                    StackValue::Fraction(frac) => {
+                        if frac.1 != 1 {
+                            break;
+                        }
+                        result.push(StackValue::Integer(frac.0));
+                    }
+                    _ => {
                         break;
                     }
-                    result.push(StackValue::Integer(frac.0));
-                }
-                _ => {
-                    break;
                 }
             }
+            if result.len() == count {
+                return Some(result);
+            }
         }
-        if result.len() == count {
-            return Some(result);
-        }
-    }
 
-    if types.contains(AcceptedTypes::FRACTIONS) {
-        let mut result = Vec::with_capacity(count);
-        for v in &stack.values[start..] {
-            match v {
-                StackValue::Fraction(frac) => result.push(StackValue::Fraction(*frac)),
-                _ => {
-                    break;
+        if types.contains(AcceptedTypes::FRACTIONS) {
+            let mut result = Vec::with_capacity(count);
+            for v in &self.values[start..] {
+                match v {
+                    StackValue::Fraction(frac) => result.push(StackValue::Fraction(*frac)),
+                    _ => {
+                        break;
+                    }
                 }
             }
+            if result.len() == count {
+                return Some(result);
+            }
         }
-        if result.len() == count {
-            return Some(result);
-        }
-    }
 
-    if types.contains(AcceptedTypes::FLOAT) {
-        let mut result = Vec::with_capacity(count);
-        for v in &stack.values[start..] {
-            match v {
-                StackValue::Fraction(frac) => {
-                    result.push(StackValue::Float(frac.0 as f64 / frac.1 as f64))
-                }
-                StackValue::Float(f) => result.push(StackValue::Float(*f)),
-                _ => {
-                    break;
+        if types.contains(AcceptedTypes::BIG) {
+            let mut result = Vec::with_capacity(count);
+            for v in &self.values[start..] {
+                match v {
+                    StackValue::Fraction(frac) => result.push(StackValue::Big(to_big(*frac))),
+                    StackValue::Big(big) => result.push(StackValue::Big(big.clone())),
+                    _ => {
+                        break;
+                    }
                 }
             }
+            if result.len() == count {
+                return Some(result);
+            }
         }
-        if result.len() == count {
-            return Some(result);
+
+        if types.contains(AcceptedTypes::FLOAT) {
+            let mut result = Vec::with_capacity(count);
+            for v in &self.values[start..] {
+                match v {
+                    StackValue::Fraction(frac) => {
+                        result.push(StackValue::Float(frac.0 as f64 / frac.1 as f64))
+                    }
+                    StackValue::Float(f) | StackValue::Inexact(f) => {
+                        result.push(StackValue::Float(*f))
+                    }
+                    StackValue::Big(big) => {
+                        result.push(StackValue::Float(big.to_f64().unwrap_or(f64::NAN)))
+                    }
+                    _ => {
+                        break;
+                    }
+                }
+            }
+            if result.len() == count {
+                return Some(result);
+            }
         }
+
+        None
     }
 
-    None
-}
+    // Why get() returns None for these arguments.
+    pub fn get_error(&self, count: usize, types: AcceptedTypes) -> Error {
+        if count > self.values.len() {
+            return Error::StackUnderflow {
+                expected: count,
+                actual: self.values.len(),
+            };
+        }
+        let start = self.values.len() - count;
+        Error::TypeMismatch {
+            expected: types,
+            found: self.values[start..]
+                .iter()
+                .find(|value| !types.accepts(value))
+                .map_or("value", StackValue::get_type_name),
+        }
+    }
 
-pub fn get_values(count: usize) -> Option<Vec<StackValue>> {
-    let stack = STACK.lock().unwrap();
+    pub fn get_values(&self, count: usize) -> Option<Vec<StackValue>> {
+        if count > self.values.len() {
+            return None;
+        }
 
-    if count > stack.values.len() {
-        return None;
+        let start = self.values.len() - count;
+        Some(self.values[start..].to_vec())
     }
 
-    let start = stack.values.len() - count;
-    Some(stack.values[start..].to_vec())
+    pub fn depth(&self) -> usize {
+        self.values.len()
+    }
 }
 
-fn get_binary_representation(value: i128) -> String {
-    let u = value as u128;
-    match (modes::get_binary_mode(), modes::get_binary_width()) {
-        (modes::BinaryMode::Signed, modes::BinaryWidth::W8) => {
-            if let Ok(v) = i8::try_from(value) {
-                return format!(" = {:02x} {:08b}", v, v);
-            }
+// Separates digits into groups from the right, e.g. dead_beef.
+fn group_digits(digits: &str, group: usize) -> String {
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(group) {
+            grouped.push('_');
         }
-        (modes::BinaryMode::Signed, modes::BinaryWidth::W16) => {
-            if let Ok(v) = i16::try_from(value) {
-                return format!(" = {:04x} {:016b}", v, v);
-            }
-        }
-        (modes::BinaryMode::Signed, modes::BinaryWidth::W32) => {
-            if let Ok(v) = i32::try_from(value) {
-                return format!(" = {:08x} {:032b}", v, v);
-            }
+        grouped.push(c);
+    }
+    grouped
+}
+
+// Formats a word of the current width in the given radix, zero padded to the full width.
+fn format_word(word: u128, radix: modes::Radix, modes: &modes::Modes) -> String {
+    let bits = modes.get_binary_width().bits() as usize;
+    match radix {
+        modes::Radix::Hex => format!(
+            "0x{}",
+            group_digits(&format!("{:0w$x}", word, w = bits.div_ceil(4)), 4)
+        ),
+        modes::Radix::Oct => format!(
+            "0o{}",
+            group_digits(&format!("{:0w$o}", word, w = bits.div_ceil(3)), 3)
+        ),
+        modes::Radix::Bin => format!("0b{}", group_digits(&format!("{:0w$b}", word, w = bits), 4)),
+        modes::Radix::Dec => modes.from_word(word).to_string(),
+    }
+}
+
+// Integers that fit in the current word are shown in the primary radix, optionally followed by
+// the enabled secondary representations.
+fn format_integer(value: &BigInt, secondary: bool, modes: &modes::Modes) -> String {
+    let (min, max) = modes.get_word_range();
+    if *value < min || *value > max {
+        return value.to_string();
+    }
+
+    let word = modes.to_word(value);
+    let radix = modes.get_radix();
+    let representations = if secondary {
+        modes.get_representations()
+    } else {
+        modes::Representations::empty()
+    };
+    let mut output = format_word(word, radix, modes);
+    let mut separator = " = ";
+    for (representation, secondary) in [
+        (modes::Representations::HEX, modes::Radix::Hex),
+        (modes::Representations::DEC, modes::Radix::Dec),
+        (modes::Representations::OCT, modes::Radix::Oct),
+        (modes::Representations::BIN, modes::Radix::Bin),
+    ] {
+        if representations.contains(representation) && secondary != radix {
+            output += separator;
+            output += &format_word(word, secondary, modes);
+            separator = " ";
         }
-        (modes::BinaryMode::Signed, modes::BinaryWidth::W64) => {
-            if let Ok(v) = i64::try_from(value) {
-                return format!(" = {:016x} {:064b}", v, v);
+    }
+    output
+}
+
+// Long lines are wrapped at spaces or group separators, continuing below the value.
+fn wrap_line(line: String, width: usize) -> Vec<String> {
+    const INDENT: &str = "      ";
+    let mut lines = Vec::new();
+    let mut rest = line.as_str();
+    let mut indent = "";
+    while indent.len() + rest.len() > width && width > INDENT.len() {
+        // Prefer breaking between representations over breaking inside one.
+        let head = &rest[..width - indent.len()];
+        let space = head
+            .rfind(' ')
+            .filter(|&i| !indent.is_empty() || i > INDENT.len());
+        match space.or_else(|| head.rfind('_')) {
+            Some(split) => {
+                lines.push(format!("{}{}", indent, rest[..=split].trim_end()));
+                rest = &rest[split + 1..];
+                indent = INDENT;
             }
+            None => break,
         }
-        (modes::BinaryMode::Unsigned, modes::BinaryWidth::W8) => {
-            if let Ok(v) = u8::try_from(u) {
-                return format!(" = {:02x} {:08b}", v, v);
-            }
+    }
+    lines.push(format!("{}{}", indent, rest));
+    lines
+}
+
+const SI_PREFIXES: [&str; 17] = [
+    "y", "z", "a", "f", "p", "n", "u", "m", "", "k", "M", "G", "T", "P", "E", "Z", "Y",
+];
+
+// Formats in engineering notation, returning the mantissa and the exponent (a multiple of 3).
+fn format_engineering(f: f64, digits: usize) -> (String, i32) {
+    if f == 0.0 {
+        return (format!("{:.digits$}", 0.0), 0);
+    }
+    let mut exponent = (f.abs().log10().floor() as i32).div_euclid(3) * 3;
+    let mut mantissa = format!("{:.digits$}", f / 10f64.powi(exponent));
+    // Rounding can push the mantissa to 1000, e.g. 999.99 with one decimal.
+    if mantissa.trim_start_matches('-').starts_with("1000") {
+        exponent += 3;
+        mantissa = format!("{:.digits$}", f / 10f64.powi(exponent));
+    }
+    (mantissa, exponent)
+}
+
+fn format_float(f: f64, format: modes::DisplayFormat) -> String {
+    if !f.is_finite() {
+        return format!("{}", f);
+    }
+    match format {
+        modes::DisplayFormat::Fix(digits) => format!("{:.digits$}", f),
+        modes::DisplayFormat::Sci(digits) => format!("{:.digits$e}", f),
+        modes::DisplayFormat::Eng(digits) => {
+            let (mantissa, exponent) = format_engineering(f, digits);
+            format!("{}e{}", mantissa, exponent)
         }
-        (modes::BinaryMode::Unsigned, modes::BinaryWidth::W16) => {
-            if let Ok(v) = u16::try_from(u) {
-                return format!(" = {:04x} {:016b}", v, v);
+        modes::DisplayFormat::Si(digits) => {
+            let (mantissa, exponent) = format_engineering(f, digits);
+            let mantissa = if mantissa.contains('.') {
+                mantissa.trim_end_matches('0').trim_end_matches('.')
+            } else {
+                &mantissa
+            };
+            match usize::try_from(exponent / 3 + 8)
+                .ok()
+                .and_then(|i| SI_PREFIXES.get(i))
+            {
+                Some(prefix) => format!("{}{}", mantissa, prefix),
+                None => format!("{}e{}", mantissa, exponent),
             }
         }
-        (modes::BinaryMode::Unsigned, modes::BinaryWidth::W32) => {
-            if let Ok(v) = u32::try_from(u) {
-                return format!(" = {:08x} {:032b}", v, v);
+        modes::DisplayFormat::All => format!("{}", f),
+    }
+}
+
+fn display(f: f64, modes: &modes::Modes) -> String {
+    format_float(f, modes.get_display_format())
+}
+
+// Formats a value for display. Without `secondary` only the primary representation is shown,
+// e.g. no binary value after integers and no decimal value after fractions.
+pub fn format_value(value: &StackValue, secondary: bool, modes: &modes::Modes) -> String {
+    match value {
+        StackValue::Float(f) => display(*f, modes),
+        StackValue::Inexact(f) => format!("~{}", display(*f, modes)),
+        StackValue::Integer(i) => format_integer(&BigInt::from(*i), secondary, modes),
+        StackValue::Fraction((num, denom)) => {
+            if *denom == 1 {
+                format_integer(&BigInt::from(*num), secondary, modes)
+            } else if secondary {
+                format!(
+                    "{}/{} = {}",
+                    num,
+                    denom,
+                    display(*num as f64 / *denom as f64, modes)
+                )
+            } else {
+                format!("{}/{}", num, denom)
             }
         }
-        (modes::BinaryMode::Unsigned, modes::BinaryWidth::W64) => {
-            if let Ok(v) = u64::try_from(u) {
-                return format!(" = {:016x} {:064b}", v, v);
+        StackValue::Big(big) => {
+            if big.denom().is_one() {
+                format_integer(big.numer(), secondary, modes)
+            } else if secondary {
+                format!(
+                    "{}/{} = {}",
+                    big.numer(),
+                    big.denom(),
+                    display(big.to_f64().unwrap_or(f64::NAN), modes)
+                )
+            } else {
+                format!("{}/{}", big.numer(), big.denom())
             }
         }
     }
-    String::new()
 }
 
-pub fn get_stack_contents() -> Vec<String> {
-    let stack = STACK.lock().unwrap();
-    let mut output: Vec<String> = Vec::new();
-    for (i, v) in stack.values.iter().enumerate() {
-        match v {
-            StackValue::Float(f) => {
-                output.push(format!("{:>4}: {:.6}", stack.values.len() - i - 1, f))
-            }
-            StackValue::Fraction((num, denom)) => {
-                if *denom == 1 {
-                    let buf: String = format!(
-                        "{:>4}: {}{}",
-                        stack.values.len() - i - 1,
-                        num,
-                        get_binary_representation(*num)
-                    );
-                    output.push(format!("{}", buf));
-                } else {
-                    output.push(format!(
-                        "{:>4}: {}/{} = {}",
-                        stack.values.len() - i - 1,
-                        num,
-                        denom,
-                        *num as f64 / *denom as f64
-                    ));
-                }
-            }
-            _ => {
-                // Unknown type, this shouldn't happen?
-                output.push("Error".to_string());
-            }
+impl Stack {
+    // Lines wider than `width` are wrapped.
+    pub fn get_stack_contents(&self, width: usize, modes: &modes::Modes) -> Vec<String> {
+        let mut output: Vec<String> = Vec::new();
+        for (i, v) in self.values.iter().enumerate() {
+            output.push(format!(
+                "{:>4}: {}",
+                self.values.len() - i - 1,
+                format_value(v, true, modes)
+            ));
         }
+        output
+            .into_iter()
+            .flat_map(|line| wrap_line(line, width))
+            .collect()
     }
-    output
 }
 
 #[cfg(test)]
@@ -272,17 +644,110 @@ mod tests {
 
     #[test]
     fn test_push_and_drop() {
-        push(StackValue::Integer(42));
-        push(StackValue::Fraction((3, 4)));
-        push(StackValue::Float(2.5));
+        let mut stack = Stack::default();
+        stack.push(StackValue::Integer(42));
+        stack.push(StackValue::Fraction((3, 4)));
+        stack.push(StackValue::Float(2.5));
 
-        let values = get_values(3).unwrap();
+        let values = stack.get_values(3).unwrap();
         assert_eq!(values.len(), 3);
 
-        drop(3);
-        assert!(get_values(1).is_none());
+        stack.drop(3);
+        assert!(stack.get_values(1).is_none());
+        assert_eq!(stack.last_x(), Some(StackValue::Fraction((5, 2))));
+    }
+
+    fn command(
+        history: &mut History,
+        stack: &mut Stack,
+        name: &str,
+        operation: impl FnOnce(&mut History, &mut Stack),
+    ) {
+        history.begin_command(stack);
+        operation(history, stack);
+        history.end_command(stack, name, true);
+    }
+
+    fn push(value: i128) -> impl FnOnce(&mut History, &mut Stack) {
+        move |_, stack| stack.push(StackValue::Integer(value))
+    }
+
+    #[test]
+    fn test_undo_and_redo() {
+        let mut history = History::default();
+        let mut stack = Stack::default();
+        command(&mut history, &mut stack, "1", push(1));
+        command(&mut history, &mut stack, "2", push(2));
+        command(&mut history, &mut stack, "add", |_, stack| {
+            stack.drop(2);
+            stack.push(StackValue::Integer(3));
+        });
+        command(&mut history, &mut stack, "swap", |_, _| {});
+
+        // The whole addition is one step, commands without effect aren't recorded.
+        assert_eq!(history.get_commands().0, vec!["1", "2", "add"]);
+        assert_eq!(history.undo(&mut stack, 1), 1);
+        assert_eq!(stack.depth(), 2);
+        assert_eq!(history.undo(&mut stack, 5), 2);
+        assert_eq!(stack.depth(), 0);
+        assert_eq!(history.get_commands().1, vec!["1", "2", "add"]);
+
+        assert_eq!(history.redo(&mut stack, 2), 2);
+        assert_eq!(stack.depth(), 2);
+        command(&mut history, &mut stack, "3", push(3));
+        assert_eq!(
+            history.get_commands(),
+            (vec!["1".into(), "2".into(), "3".into()], vec![])
+        );
+    }
 
-        let backup = STACKBACKUP.lock().unwrap();
-        assert_eq!(backup.len(), 4);
+    #[test]
+    fn test_history_depth() {
+        let mut history = History::default();
+        let mut stack = Stack::default();
+        history.set_depth(2);
+        for i in 0..5 {
+            command(&mut history, &mut stack, &i.to_string(), push(i));
+        }
+        assert_eq!(history.get_commands().0, vec!["3", "4"]);
+
+        // Nested commands are a single step.
+        command(&mut history, &mut stack, "run", |history, stack| {
+            command(history, stack, "5", push(5));
+            command(history, stack, "6", push(6));
+        });
+        assert_eq!(history.get_commands().0, vec!["4", "run"]);
+    }
+
+    #[test]
+    fn test_big_conversion() {
+        assert_eq!(from_big(&to_big((-3, 4))), Some((-3, 4)));
+
+        let too_big = to_big((i128::MAX, 1)) * to_big((2, 1));
+        assert_eq!(from_big(&too_big), None);
+        assert_eq!(from_big(&(too_big / to_big((2, 1)))), Some((i128::MAX, 1)));
+    }
+
+    #[test]
+    fn test_format_float() {
+        use modes::DisplayFormat::*;
+
+        assert_eq!(format_float(1234.5678, Fix(2)), "1234.57");
+        assert_eq!(format_float(1234.5678, Sci(3)), "1.235e3");
+        assert_eq!(format_float(12345.678, Eng(2)), "12.35e3");
+        assert_eq!(format_float(0.000999999, Eng(2)), "1.00e-3");
+        assert_eq!(format_float(4700.0, Si(3)), "4.7k");
+        assert_eq!(format_float(220e-9, Si(3)), "220n");
+        assert_eq!(format_float(0.1, All), "0.1");
+    }
+
+    #[test]
+    fn test_grouping() {
+        assert_eq!(group_digits("deadbeef", 4), "dead_beef");
+        assert_eq!(group_digits("1777", 3), "1_777");
+        assert_eq!(
+            wrap_line("   0: 0b1010_0101_1010_0101".to_string(), 20),
+            vec!["   0: 0b1010_0101_", "      1010_0101"]
+        );
     }
 }
@@ -1,65 +1,405 @@
 use crate::CmdResult;
-use crate::help;
-use crate::stack;
+use crate::calculator::{Calculator, View};
+use crate::command::{Builtin, Category};
+use crate::error::Error;
+use crate::modes;
+use crate::stack::{AcceptedTypes, StackValue};
 
-// TODO: There should be some error handling here.
+// Commands rearranging the stack. Positions count from 0 at the top, like the stack display.
 
-pub fn drop() -> CmdResult {
-    stack::drop(1);
-    CmdResult::Success
+// Replaces the top `count` values with the result of `rearrange`, which gets them bottom first.
+fn rearrange(
+    calc: &mut Calculator,
+    count: usize,
+    rearrange: fn(&[StackValue]) -> Vec<StackValue>,
+) -> CmdResult {
+    match calc.stack.get_values(count) {
+        Some(values) => {
+            calc.stack.replace(count, rearrange(&values));
+            CmdResult::Success
+        }
+        None => CmdResult::Error(calc.stack.get_error(count, AcceptedTypes::all())),
+    }
 }
 
-pub fn dup() -> CmdResult {
-    let stack = stack::get_values(1);
-    if let Some(value) = stack {
-        stack::push(value[0]);
-    }
-    CmdResult::Success
+// The position or count argument, given inline (`pick 2`) or taken from the stack.
+fn get_count(calc: &mut Calculator, args: &[&str]) -> Result<usize, Error> {
+    modes::get_argument(calc, args, 0..=u32::MAX, "Invalid position or count").map(|n| n as usize)
+}
+
+pub fn drop(calc: &mut Calculator) -> CmdResult {
+    rearrange(calc, 1, |_| vec![])
+}
+
+pub fn dup(calc: &mut Calculator) -> CmdResult {
+    rearrange(calc, 1, |v| vec![v[0].clone(), v[0].clone()])
+}
+
+pub fn swap(calc: &mut Calculator) -> CmdResult {
+    rearrange(calc, 2, |v| vec![v[1].clone(), v[0].clone()])
+}
+
+fn over(calc: &mut Calculator) -> CmdResult {
+    rearrange(calc, 2, |v| vec![v[0].clone(), v[1].clone(), v[0].clone()])
+}
+
+fn rot(calc: &mut Calculator) -> CmdResult {
+    rearrange(calc, 3, |v| vec![v[1].clone(), v[2].clone(), v[0].clone()])
+}
+
+fn rot_back(calc: &mut Calculator) -> CmdResult {
+    rearrange(calc, 3, |v| vec![v[2].clone(), v[0].clone(), v[1].clone()])
+}
+
+fn nip(calc: &mut Calculator) -> CmdResult {
+    rearrange(calc, 2, |v| vec![v[1].clone()])
+}
+
+fn tuck(calc: &mut Calculator) -> CmdResult {
+    rearrange(calc, 2, |v| vec![v[1].clone(), v[0].clone(), v[1].clone()])
 }
 
-pub fn swap() -> CmdResult {
-    let stack = stack::get_values(2);
-    if let Some(values) = stack {
-        stack::drop(2);
-        stack::push(values[1]);
-        stack::push(values[0]);
+// Applies `operation` to the top `n` + `extra` values, where n is the command's argument.
+fn with_count(
+    calc: &mut Calculator,
+    args: &[&str],
+    extra: usize,
+    operation: fn(&mut Vec<StackValue>),
+) -> CmdResult {
+    let n = match get_count(calc, args) {
+        Ok(n) => n,
+        Err(e) => return CmdResult::Error(e),
+    };
+    let count = n.saturating_add(extra);
+    match calc.stack.get_values(count) {
+        Some(mut values) => {
+            operation(&mut values);
+            calc.stack.replace(count, values);
+            CmdResult::Success
+        }
+        None => CmdResult::Error(calc.stack.get_error(count, AcceptedTypes::all())),
     }
+}
+
+fn pick(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    with_count(calc, args, 1, |v| v.push(v[0].clone()))
+}
+
+fn roll(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    with_count(calc, args, 1, |v| v.rotate_left(1))
+}
+
+fn roll_down(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    with_count(calc, args, 1, |v| v.rotate_right(1))
+}
+
+fn dup_n(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    with_count(calc, args, 0, |v| v.extend_from_within(..))
+}
+
+fn drop_n(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    with_count(calc, args, 0, |v| v.clear())
+}
+
+fn depth(calc: &mut Calculator) -> CmdResult {
+    let depth = calc.stack.depth();
+    calc.stack.push(StackValue::Integer(depth as i128));
     CmdResult::Success
 }
 
-pub fn undo() -> CmdResult {
-    stack::undo();
+fn clear(calc: &mut Calculator) -> CmdResult {
+    calc.stack.clear();
     CmdResult::Success
 }
 
-pub fn commands(cmd: &str) -> CmdResult {
-    match cmd {
-        "drop" => drop(),
-        "dup" => dup(),
-        "swap" => swap(),
-        "undo" => undo(),
-        _ => CmdResult::NoMatch,
+fn last_x(calc: &mut Calculator) -> CmdResult {
+    match calc.stack.last_x() {
+        Some(value) => {
+            calc.stack.push(value);
+            CmdResult::Success
+        }
+        None => CmdResult::Error(Error::Failed("No last x value")),
     }
 }
 
-pub const HELP: help::Category = help::Category {
-    category: "Stack Manipulations",
-    commands: &[
-        help::Cmd {
-            command: "drop",
-            help: "Removes the top value from the stack.",
-        },
-        help::Cmd {
-            command: "dup",
-            help: "Duplicates the top value on the stack.",
-        },
-        help::Cmd {
-            command: "swap",
-            help: "Swaps the top two values on the stack.",
-        },
-        help::Cmd {
-            command: "undo",
-            help: "Undoes the last stack manipulation.",
+// The number of steps for undo and redo. Unlike other arguments it's never taken from the stack,
+// plain undo is one step.
+fn get_steps(args: &[&str]) -> Result<usize, Error> {
+    match args {
+        [] => Ok(1),
+        [arg] => match arg.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(Error::Argument("Invalid number of steps")),
         },
+        _ => Err(Error::Argument("Wrong number of arguments")),
+    }
+}
+
+// Undo and redo work on whole commands, a script can't undo its own steps or the ones before it.
+pub fn undo(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    if calc.history.is_nested() {
+        return CmdResult::Error(Error::Failed("Can't undo inside a script"));
+    }
+    match get_steps(args) {
+        Ok(steps) if calc.history.undo(&mut calc.stack, steps) > 0 => CmdResult::Success,
+        Ok(_) => CmdResult::Error(Error::Failed("Nothing to undo")),
+        Err(e) => CmdResult::Error(e),
+    }
+}
+
+fn redo(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    if calc.history.is_nested() {
+        return CmdResult::Error(Error::Failed("Can't redo inside a script"));
+    }
+    match get_steps(args) {
+        Ok(steps) if calc.history.redo(&mut calc.stack, steps) > 0 => CmdResult::Success,
+        Ok(_) => CmdResult::Error(Error::Failed("Nothing to redo")),
+        Err(e) => CmdResult::Error(e),
+    }
+}
+
+fn set_history_depth(calc: &mut Calculator, args: &[&str]) -> CmdResult {
+    match modes::get_argument(calc, args, 0..=u32::MAX, "Invalid history depth") {
+        Ok(depth) => {
+            calc.history.set_depth(depth as usize);
+            CmdResult::Success
+        }
+        Err(e) => CmdResult::Error(e),
+    }
+}
+
+fn history(calc: &mut Calculator) -> CmdResult {
+    calc.view = View::History;
+    CmdResult::Success
+}
+
+// Lists the commands in the order they were run. Each is labeled with the steps for undo or
+// redo to get back to the state before or after it.
+pub fn get_history(calc: &Calculator) -> Vec<String> {
+    let (undo, redo) = calc.history.get_commands();
+    let mut lines: Vec<String> = undo
+        .iter()
+        .enumerate()
+        .map(|(i, command)| format!("{:>4}: {}", undo.len() - i, command))
+        .collect();
+    lines.push("      <- current".to_string());
+    lines.extend(
+        redo.iter()
+            .enumerate()
+            .map(|(i, command)| format!("{:>4}: {}", format!("+{}", i + 1), command)),
+    );
+    lines
+}
+
+pub const COMMANDS: Category = Category {
+    name: "Stack Manipulations",
+    commands: &[
+        Builtin::new(
+            "drop",
+            "Removes the top value from the stack.",
+            |calc, _| drop(calc),
+        )
+        .with_operands(1, AcceptedTypes::all()),
+        Builtin::new(
+            "dup",
+            "Duplicates the top value on the stack.",
+            |calc, _| dup(calc),
+        )
+        .with_operands(1, AcceptedTypes::all()),
+        Builtin::new(
+            "swap",
+            "Swaps the top two values on the stack.",
+            |calc, _| swap(calc),
+        )
+        .with_operands(2, AcceptedTypes::all()),
+        Builtin::new("over", "Copies the second value to the top.", |calc, _| {
+            over(calc)
+        })
+        .with_operands(2, AcceptedTypes::all()),
+        Builtin::new("rot", "Moves the third value to the top.", |calc, _| {
+            rot(calc)
+        })
+        .with_operands(3, AcceptedTypes::all()),
+        Builtin::new(
+            "-rot",
+            "Moves the top value down to the third position.",
+            |calc, _| rot_back(calc),
+        )
+        .with_operands(3, AcceptedTypes::all()),
+        Builtin::new("nip", "Removes the second value.", |calc, _| nip(calc))
+            .with_operands(2, AcceptedTypes::all()),
+        Builtin::new(
+            "tuck",
+            "Copies the top value below the second value.",
+            |calc, _| tuck(calc),
+        )
+        .with_operands(2, AcceptedTypes::all()),
+        Builtin::new(
+            "pick",
+            "Copies the value at position n to the top. 0 pick is dup.",
+            pick,
+        )
+        .with_arguments(&["n"]),
+        Builtin::new(
+            "roll",
+            "Moves the value at position n to the top. 2 roll is rot.",
+            roll,
+        )
+        .with_arguments(&["n"]),
+        Builtin::new(
+            "rolld",
+            "Moves the top value down to position n. 2 rolld is -rot.",
+            roll_down,
+        )
+        .with_arguments(&["n"]),
+        Builtin::new("dupn", "Duplicates the top n values.", dup_n).with_arguments(&["n"]),
+        Builtin::new("dropn", "Removes the top n values.", drop_n).with_arguments(&["n"]),
+        Builtin::new(
+            "depth",
+            "Pushes the number of values on the stack.",
+            |calc, _| depth(calc),
+        ),
+        Builtin::new("clear", "Removes all values from the stack.", |calc, _| {
+            clear(calc)
+        }),
+        Builtin::new(
+            "lastx",
+            "Pushes the top value used by the last operation.",
+            |calc, _| last_x(calc),
+        ),
+        Builtin::new(
+            "undo",
+            "Undoes the last n commands, or the last one without n.",
+            undo,
+        )
+        .with_arguments(&["n"]),
+        Builtin::new(
+            "redo",
+            "Redoes the last n undone commands, or the last one without n.",
+            redo,
+        )
+        .with_arguments(&["n"]),
+        Builtin::new(
+            "history",
+            "Lists the commands that can be undone or redone, labeled with the steps to get there.",
+            |calc, _| history(calc),
+        ),
+        Builtin::new(
+            "undo_depth",
+            "Sets the maximum number of undo steps, 0 for no limit.",
+            set_history_depth,
+        )
+        .with_arguments(&["n"]),
     ],
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup(values: &[i128]) -> Calculator {
+        let mut calc = Calculator::new();
+        for value in values {
+            calc.stack.push(StackValue::Integer(*value));
+        }
+        calc
+    }
+
+    fn contents(calc: &Calculator) -> Vec<i128> {
+        calc.stack
+            .get_values(calc.stack.depth())
+            .unwrap()
+            .iter()
+            .map(|v| match v {
+                StackValue::Fraction((n, 1)) => *n,
+                _ => panic!("unexpected value {:?}", v),
+            })
+            .collect()
+    }
+
+    fn check(values: &[i128], cmd: &str, expected: &[i128]) {
+        let mut calc = setup(values);
+        assert!(calc.exec(cmd).is_ok(), "{}", cmd);
+        assert_eq!(contents(&calc), expected, "{}", cmd);
+    }
+
+    fn check_error(values: &[i128], cmd: &str) {
+        let mut calc = setup(values);
+        assert!(calc.exec(cmd).is_err(), "{}", cmd);
+    }
+
+    #[test]
+    fn test_basic_words() {
+        check(&[1, 2], "drop", &[1]);
+        check(&[1, 2], "dup", &[1, 2, 2]);
+        check(&[1, 2], "swap", &[2, 1]);
+        check(&[1, 2], "over", &[1, 2, 1]);
+        check(&[1, 2, 3], "rot", &[2, 3, 1]);
+        check(&[1, 2, 3], "-rot", &[3, 1, 2]);
+        check(&[1, 2], "nip", &[2]);
+        check(&[1, 2], "tuck", &[2, 1, 2]);
+        check(&[1, 2], "depth", &[1, 2, 2]);
+        check(&[1, 2], "clear", &[]);
+    }
+
+    #[test]
+    fn test_counted_words() {
+        check(&[1, 2, 3], "pick 0", &[1, 2, 3, 3]);
+        check(&[1, 2, 3], "pick 2", &[1, 2, 3, 1]);
+        check(&[1, 2, 3, 1], "pick", &[1, 2, 3, 2]);
+        check(&[1, 2, 3], "roll 2", &[2, 3, 1]);
+        check(&[1, 2, 3], "rolld 2", &[3, 1, 2]);
+        check(&[1, 2, 3], "roll 0", &[1, 2, 3]);
+        check(&[1, 2, 3], "dupn 2", &[1, 2, 3, 2, 3]);
+        check(&[1, 2, 3], "dropn 2", &[1]);
+        check(&[1, 2, 3], "dropn 0", &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_shallow_stack() {
+        for cmd in ["drop", "dup", "nip", "lastx"] {
+            check_error(&[], cmd);
+        }
+        for cmd in ["swap", "over", "tuck"] {
+            check_error(&[1], cmd);
+        }
+        for cmd in [
+            "rot", "-rot", "pick 2", "roll 2", "rolld 2", "dupn 3", "dropn 3",
+        ] {
+            check_error(&[1, 2], cmd);
+        }
+        check_error(&[1, 2], "pick");
+        check_error(&[1, -1], "pick");
+        // Counts that don't fit are rejected, not truncated.
+        check_error(&[1, 2, 3, 4294967297], "pick");
+        check_error(&[1, 2, 3, 4294967297], "dropn");
+    }
+
+    #[test]
+    fn test_last_x() {
+        let mut calc = setup(&[1, 2]);
+        calc.stack.drop(2);
+        calc.stack.push(StackValue::Integer(3));
+        assert!(calc.exec("swap").is_err());
+        assert!(calc.exec("lastx").is_ok());
+        assert_eq!(contents(&calc), [3, 2]);
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut calc = setup(&[1, 2, 3]);
+        assert!(calc.exec("rot").is_ok());
+        assert!(calc.exec("nip").is_ok());
+        assert!(calc.exec("undo 2").is_ok());
+        assert_eq!(contents(&calc), [1, 2, 3]);
+        assert!(calc.exec("redo").is_ok());
+        assert_eq!(contents(&calc), [2, 3, 1]);
+        assert!(calc.exec("undo 0").is_err());
+        assert!(calc.exec("undo 2").is_ok());
+        assert!(calc.exec("undo").is_err());
+        assert_eq!(
+            get_history(&calc),
+            ["      <- current", "  +1: rot", "  +2: nip"]
+        );
+    }
+}